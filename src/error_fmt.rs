@@ -4,38 +4,168 @@ use std::fmt::Display;
 pub struct Error {
     pub message: String,
     pub text: String,
+    pub source_name: String,
     pub line: usize,
     pub col: usize,
+    /// Number of columns ending at `col` to underline.
+    pub width: usize,
 }
 
 impl Error {
-    pub fn new(message: String, text: String, line: usize, col: usize) -> Self {
+    pub fn new(
+        message: String,
+        text: String,
+        source_name: String,
+        line: usize,
+        col: usize,
+    ) -> Self {
         Error {
             message,
             text,
+            source_name,
             line,
             col,
+            width: 1,
         }
     }
+
+    pub fn with_width(mut self, width: usize) -> Self {
+        self.width = width.max(1);
+        self
+    }
+
+    /// Renders the annotated source under `message` in place of our own.
+    fn render(&self, message: &str) -> String {
+        // Lines start with "|{line}. " so the carets are shifted by its width
+        let indent = self.line.to_string().len() + 2 + self.col;
+        format!(
+            "{0}\n--> {4}:{1}:{5}\n|\n|{1}. {2}\n|{3}{6} \n",
+            message,
+            self.line,
+            self.text.trim_end(),
+            " ".repeat(indent.saturating_sub(self.width)),
+            self.source_name,
+            self.col,
+            "↑".repeat(self.width),
+        )
+    }
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{0}\n|\n|{1}. {2}\n|{3}↑ \n",
-            self.message,
-            self.line,
-            self.text.trim(),
-            std::iter::repeat(" ")
-                .take(self.col + 2)
-                .collect::<String>()
-        )
+        write!(f, "{}", self.render(&self.message))
+    }
+}
+
+pub fn report_errors(errors: &[Error]) {
+    print!("{}", format_errors(errors, None))
+}
+
+/// Renders the errors for printing. A run of errors with the same message on
+/// the same line is shown once with its count. With a `max`, only that many
+/// entries are shown, followed by a line counting the errors left out.
+pub fn format_errors(errors: &[Error], max: Option<usize>) -> String {
+    let runs: Vec<&[Error]> = errors
+        .chunk_by(|a, b| a.line == b.line && a.message == b.message)
+        .collect();
+    let shown = &runs[..max.unwrap_or(runs.len()).min(runs.len())];
+    let mut out = String::new();
+    for run in shown {
+        out += &match run.len() {
+            1 => run[0].to_string(),
+            count => run[0].render(&format!("{} ({} times)", run[0].message, count)),
+        };
+    }
+    match errors.len() - shown.iter().map(|run| run.len()).sum::<usize>() {
+        0 => {}
+        1 => out += "... and 1 more error.\n",
+        rest => out += &format!("... and {} more errors.\n", rest),
     }
+    out
 }
 
-pub fn report_errors(errors: &Vec<Error>) {
-    for error in errors {
-        print!("{}", error)
+/// Prints the errors as one JSON array for editor tooling.
+pub fn report_errors_json(errors: &[Error]) {
+    println!("{}", to_json(errors))
+}
+
+/// Serializes the errors to a JSON array of objects with `line`, `col`,
+/// `message` and `text` fields.
+pub fn to_json(errors: &[Error]) -> String {
+    let objects: Vec<String> = errors
+        .iter()
+        .map(|e| {
+            format!(
+                "{{\"line\":{},\"col\":{},\"message\":{},\"text\":{}}}",
+                e.line,
+                e.col,
+                json_string(&e.message),
+                json_string(&e.text)
+            )
+        })
+        .collect();
+    format!("[{}]", objects.join(","))
+}
+
+fn json_string(s: &str) -> String {
+    let mut json = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::try_scan_tokens;
+
+    #[test]
+    fn test_format_errors_grouped() {
+        let errors = try_scan_tokens("1 + ~~~~", "<test>").unwrap_err();
+        assert_eq!(
+            "Lexical Error: Unexpected character. (4 times)\n--> <test>:1:5\n|\n|1. 1 + ~~~~\n|       ↑ \n",
+            format_errors(&errors, None)
+        );
+
+        // Different lines and different messages are kept apart
+        let errors = try_scan_tokens("~~\n~ \"a", "<test>").unwrap_err();
+        let report = format_errors(&errors, None);
+        assert!(report.starts_with("Lexical Error: Unexpected character. (2 times)\n"));
+        assert!(report.contains("\nLexical Error: Unexpected character.\n--> <test>:2:1\n"));
+        assert!(report.contains("\nLexical Error: Unterminated string."));
+    }
+
+    #[test]
+    fn test_format_errors_capped() {
+        let errors = try_scan_tokens("~\n~\n~\n~", "<test>").unwrap_err();
+        let all = format_errors(&errors, None);
+        assert_eq!(4, all.matches("Unexpected character.").count());
+        assert_eq!(all, format_errors(&errors, Some(4)));
+
+        let capped = format_errors(&errors, Some(2));
+        assert_eq!(2, capped.matches("Unexpected character.").count());
+        assert!(capped.ends_with(" \n... and 2 more errors.\n"));
+        assert!(format_errors(&errors, Some(3)).ends_with("... and 1 more error.\n"));
+    }
+
+    #[test]
+    fn test_to_json() {
+        let errors = try_scan_tokens("\"a\" +\n  ~", "<test>").unwrap_err();
+        assert_eq!(
+            r#"[{"line":2,"col":3,"message":"Lexical Error: Unexpected character.","text":"  ~"}]"#,
+            to_json(&errors)
+        );
+        assert_eq!("[]", to_json(&[]));
+        assert_eq!(r#""say \"hi\"\\\n""#, json_string("say \"hi\"\\\n"));
     }
 }
@@ -1,65 +1,674 @@
-use crate::token::Token;
-use std::any::Any;
-use std::fmt::{Debug, Display};
+use crate::parser::precedence::precedence;
+use crate::token::{Literal, Token, TokenType};
+use std::fmt::Display;
 
-pub trait AnyDebug: Any + Debug {}
-impl<T> AnyDebug for T where T: Any + Debug {}
+/// Identifies a node within the tree it was parsed into, indexing the
+/// `SourceMap` built alongside it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ExprId(pub usize);
+
+/// Where a node's source starts and ends as `(line, col)` pairs, with
+/// columns relative to their line like `Token::span`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Span {
+    pub start: (usize, usize),
+    pub end: (usize, usize),
+}
+
+/// The spans of parsed nodes, so values and errors can be traced back to
+/// the source that produced them.
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    spans: Vec<Span>,
+}
+
+impl SourceMap {
+    pub fn span(&self, id: ExprId) -> Option<&Span> {
+        self.spans.get(id.0)
+    }
+
+    pub(crate) fn add(&mut self, span: Span) -> ExprId {
+        self.spans.push(span);
+        ExprId(self.spans.len() - 1)
+    }
+}
+
+/// A borrowed view of a concrete expression node.
+///
+/// Lets a `dyn Expr` be dispatched to the matching `Visitor` method without
+/// making `Expr` itself generic.
+pub enum Node<'a> {
+    ArrayLit(&'a ArrayLit),
+    Bin(&'a Bin),
+    Call(&'a Call),
+    Cond(&'a Cond),
+    Grp(&'a Grp),
+    Index(&'a Index),
+    Lit(&'a Lit),
+    Logical(&'a Logical),
+    MapLit(&'a MapLit),
+    Seq(&'a Seq),
+    Un(&'a Un),
+    Var(&'a Var),
+}
+
+pub trait Expr: Display {
+    fn node(&self) -> Node<'_>;
+    fn id(&self) -> ExprId;
+}
+
+impl dyn Expr + '_ {
+    /// Dispatches to the `Visitor` method for the concrete node type.
+    pub fn accept<R>(&self, visitor: &mut dyn Visitor<R>) -> R {
+        match self.node() {
+            Node::ArrayLit(expr) => visitor.visit_array_lit(expr),
+            Node::Bin(expr) => visitor.visit_bin(expr),
+            Node::Call(expr) => visitor.visit_call(expr),
+            Node::Cond(expr) => visitor.visit_cond(expr),
+            Node::Grp(expr) => visitor.visit_grp(expr),
+            Node::Index(expr) => visitor.visit_index(expr),
+            Node::Lit(expr) => visitor.visit_lit(expr),
+            Node::Logical(expr) => visitor.visit_logical(expr),
+            Node::MapLit(expr) => visitor.visit_map_lit(expr),
+            Node::Seq(expr) => visitor.visit_seq(expr),
+            Node::Un(expr) => visitor.visit_un(expr),
+            Node::Var(expr) => visitor.visit_var(expr),
+        }
+    }
+}
+
+/// Operation over the expression tree producing an `R` for each node.
+pub trait Visitor<R> {
+    fn visit_array_lit(&mut self, expr: &ArrayLit) -> R;
+    fn visit_bin(&mut self, expr: &Bin) -> R;
+    fn visit_call(&mut self, expr: &Call) -> R;
+    fn visit_cond(&mut self, expr: &Cond) -> R;
+    fn visit_grp(&mut self, expr: &Grp) -> R;
+    fn visit_index(&mut self, expr: &Index) -> R;
+    fn visit_lit(&mut self, expr: &Lit) -> R;
+    fn visit_logical(&mut self, expr: &Logical) -> R;
+    fn visit_map_lit(&mut self, expr: &MapLit) -> R;
+    fn visit_seq(&mut self, expr: &Seq) -> R;
+    fn visit_un(&mut self, expr: &Un) -> R;
+    fn visit_var(&mut self, expr: &Var) -> R;
+}
+
+/// Prints an expression in parenthesized prefix form, e.g. `(* (grp (+ 1 2)) 3)`.
+pub struct AstPrinter;
+
+impl Visitor<String> for AstPrinter {
+    fn visit_array_lit(&mut self, expr: &ArrayLit) -> String {
+        let mut out = String::from("(list");
+        for element in &expr.elements {
+            out += &format!(" {}", element.accept(self));
+        }
+        out + ")"
+    }
+
+    fn visit_bin(&mut self, expr: &Bin) -> String {
+        format!(
+            "({} {} {})",
+            expr.operator,
+            expr.left.accept(self),
+            expr.right.accept(self)
+        )
+    }
+
+    fn visit_call(&mut self, expr: &Call) -> String {
+        let mut out = format!("(call {}", expr.callee.accept(self));
+        for arg in &expr.arguments {
+            out += &format!(" {}", arg.accept(self));
+        }
+        out + ")"
+    }
+
+    fn visit_cond(&mut self, expr: &Cond) -> String {
+        format!(
+            "({} ? {} : {})",
+            expr.cond.accept(self),
+            expr.cons.accept(self),
+            expr.alt.accept(self)
+        )
+    }
+
+    fn visit_grp(&mut self, expr: &Grp) -> String {
+        format!("(grp {})", expr.expression.accept(self))
+    }
+
+    fn visit_index(&mut self, expr: &Index) -> String {
+        format!(
+            "(index {} {})",
+            expr.object.accept(self),
+            expr.index.accept(self)
+        )
+    }
+
+    fn visit_lit(&mut self, expr: &Lit) -> String {
+        match &expr.value {
+            // Quoted and escaped so the dump reads like the source did
+            Literal::String(s) => format!("{:?}", s),
+            value => value.to_string(),
+        }
+    }
+
+    fn visit_logical(&mut self, expr: &Logical) -> String {
+        format!(
+            "({} {} {})",
+            expr.operator,
+            expr.left.accept(self),
+            expr.right.accept(self)
+        )
+    }
+
+    fn visit_map_lit(&mut self, expr: &MapLit) -> String {
+        let mut out = String::from("(map");
+        for (key, value) in &expr.entries {
+            out += &format!(" ({} {})", key.accept(self), value.accept(self));
+        }
+        out + ")"
+    }
+
+    fn visit_seq(&mut self, expr: &Seq) -> String {
+        let mut out = String::from("(seq");
+        for expr in &expr.exprs {
+            out += &format!(" {}", expr.accept(self));
+        }
+        out + ")"
+    }
+
+    fn visit_un(&mut self, expr: &Un) -> String {
+        format!("({} {})", expr.operator, expr.right.accept(self))
+    }
+
+    fn visit_var(&mut self, expr: &Var) -> String {
+        expr.name.to_string()
+    }
+}
+
+// Binding strengths for `SourcePrinter`, loosest first. Binary operators sit
+// between `COND` and `UNARY`, offset from their parser precedence.
+const SEQ: u8 = 0;
+const COND: u8 = 1;
+const UNARY: u8 = 13;
+const POSTFIX: u8 = 14;
+const ATOM: u8 = 15;
+
+/// Prints an expression back as Lox source, e.g. `(1 + 2) * 3`, keeping
+/// only the parentheses that precedence requires.
+pub struct SourcePrinter;
+
+impl SourcePrinter {
+    /// Prints `expr` where the surrounding syntax needs it to bind at least
+    /// as tightly as `min`, wrapping it in parentheses otherwise.
+    pub fn print(&mut self, expr: &dyn Expr, min: u8) -> String {
+        let (strength, source) = match expr.node() {
+            // The parser's groups are dropped, parentheses are re-derived here
+            Node::Grp(grp) => return self.print(grp.expression.as_ref(), min),
+            Node::Seq(_) => (SEQ, expr.accept(self)),
+            Node::Cond(_) => (COND, expr.accept(self)),
+            Node::Bin(Bin { operator, .. }) | Node::Logical(Logical { operator, .. }) => {
+                (binding(operator), expr.accept(self))
+            }
+            Node::Un(_) => (UNARY, expr.accept(self)),
+            Node::Call(_) | Node::Index(_) => (POSTFIX, expr.accept(self)),
+            _ => (ATOM, expr.accept(self)),
+        };
+        match strength < min {
+            true => format!("({})", source),
+            false => source,
+        }
+    }
+
+    fn list(&mut self, exprs: &[Box<dyn Expr>], min: u8) -> String {
+        exprs
+            .iter()
+            .map(|expr| self.print(expr.as_ref(), min))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn infix(&mut self, left: &dyn Expr, operator: &Token, right: &dyn Expr) -> String {
+        // Left associative, so an equal right operand needs parentheses
+        let strength = binding(operator);
+        format!(
+            "{} {} {}",
+            self.print(left, strength),
+            operator,
+            self.print(right, strength + 1)
+        )
+    }
+}
+
+fn binding(operator: &Token) -> u8 {
+    precedence(operator.token_type).map_or(ATOM, |p| p + COND)
+}
+
+impl Visitor<String> for SourcePrinter {
+    fn visit_array_lit(&mut self, expr: &ArrayLit) -> String {
+        format!("[{}]", self.list(&expr.elements, COND))
+    }
+
+    fn visit_bin(&mut self, expr: &Bin) -> String {
+        self.infix(expr.left.as_ref(), &expr.operator, expr.right.as_ref())
+    }
+
+    fn visit_call(&mut self, expr: &Call) -> String {
+        let callee = self.print(expr.callee.as_ref(), POSTFIX);
+        format!("{}({})", callee, self.list(&expr.arguments, COND))
+    }
+
+    fn visit_cond(&mut self, expr: &Cond) -> String {
+        // Right associative, so only the alternative may be another ternary
+        format!(
+            "{} ? {} : {}",
+            self.print(expr.cond.as_ref(), COND + 1),
+            self.print(expr.cons.as_ref(), SEQ),
+            self.print(expr.alt.as_ref(), COND)
+        )
+    }
+
+    fn visit_grp(&mut self, expr: &Grp) -> String {
+        self.print(expr.expression.as_ref(), SEQ)
+    }
+
+    fn visit_index(&mut self, expr: &Index) -> String {
+        format!(
+            "{}[{}]",
+            self.print(expr.object.as_ref(), POSTFIX),
+            self.print(expr.index.as_ref(), SEQ)
+        )
+    }
+
+    fn visit_lit(&mut self, expr: &Lit) -> String {
+        AstPrinter.visit_lit(expr)
+    }
+
+    fn visit_logical(&mut self, expr: &Logical) -> String {
+        self.infix(expr.left.as_ref(), &expr.operator, expr.right.as_ref())
+    }
+
+    fn visit_map_lit(&mut self, expr: &MapLit) -> String {
+        let entries: Vec<String> = expr
+            .entries
+            .iter()
+            .map(|(key, value)| {
+                let key = self.print(key.as_ref(), COND);
+                format!("{}: {}", key, self.print(value.as_ref(), COND))
+            })
+            .collect();
+        format!("{{{}}}", entries.join(", "))
+    }
+
+    fn visit_seq(&mut self, expr: &Seq) -> String {
+        self.list(&expr.exprs, COND)
+    }
+
+    fn visit_un(&mut self, expr: &Un) -> String {
+        let right = self.print(expr.right.as_ref(), UNARY);
+        // `- -1` must not run together into the `--` token
+        match expr.operator.token_type == TokenType::Minus && right.starts_with('-') {
+            true => format!("{} {}", expr.operator, right),
+            false => format!("{}{}", expr.operator, right),
+        }
+    }
+
+    fn visit_var(&mut self, expr: &Var) -> String {
+        expr.name.to_string()
+    }
+}
+
+pub struct ArrayLit {
+    pub id: ExprId,
+    pub elements: Vec<Box<dyn Expr>>,
+}
+impl Expr for ArrayLit {
+    fn node(&self) -> Node<'_> {
+        Node::ArrayLit(self)
+    }
+
+    fn id(&self) -> ExprId {
+        self.id
+    }
+}
+impl Display for ArrayLit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", AstPrinter.visit_array_lit(self))
+    }
+}
 
-pub trait Expr: Display {}
 pub struct Bin {
+    pub id: ExprId,
     pub left: Box<dyn Expr>,
     pub operator: Token,
     pub right: Box<dyn Expr>,
 }
-impl Expr for Bin {}
+impl Expr for Bin {
+    fn node(&self) -> Node<'_> {
+        Node::Bin(self)
+    }
+
+    fn id(&self) -> ExprId {
+        self.id
+    }
+}
 impl Display for Bin {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "({} {} {})", self.operator, self.left, self.right)
+        write!(f, "{}", AstPrinter.visit_bin(self))
+    }
+}
+
+pub struct Call {
+    pub id: ExprId,
+    pub callee: Box<dyn Expr>,
+    pub paren: Token,
+    pub arguments: Vec<Box<dyn Expr>>,
+}
+impl Expr for Call {
+    fn node(&self) -> Node<'_> {
+        Node::Call(self)
+    }
+
+    fn id(&self) -> ExprId {
+        self.id
+    }
+}
+impl Display for Call {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", AstPrinter.visit_call(self))
     }
 }
 
 pub struct Cond {
+    pub id: ExprId,
     pub cond: Box<dyn Expr>,
     pub cons: Box<dyn Expr>,
     pub alt: Box<dyn Expr>,
 }
-impl Expr for Cond {}
+impl Expr for Cond {
+    fn node(&self) -> Node<'_> {
+        Node::Cond(self)
+    }
+
+    fn id(&self) -> ExprId {
+        self.id
+    }
+}
 impl Display for Cond {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "({} ? {} : {})", self.cond, self.cons, self.alt)
+        write!(f, "{}", AstPrinter.visit_cond(self))
     }
 }
 
 pub struct Grp {
+    pub id: ExprId,
+    /// The opening paren, kept for diagnostics.
+    pub paren: Token,
     pub expression: Box<dyn Expr>,
 }
-impl Expr for Grp {}
+impl Expr for Grp {
+    fn node(&self) -> Node<'_> {
+        Node::Grp(self)
+    }
+
+    fn id(&self) -> ExprId {
+        self.id
+    }
+}
 impl Display for Grp {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "(grp {})", self.expression)
+        write!(f, "{}", AstPrinter.visit_grp(self))
+    }
+}
+
+pub struct Index {
+    pub id: ExprId,
+    pub object: Box<dyn Expr>,
+    pub bracket: Token,
+    pub index: Box<dyn Expr>,
+}
+impl Expr for Index {
+    fn node(&self) -> Node<'_> {
+        Node::Index(self)
+    }
+
+    fn id(&self) -> ExprId {
+        self.id
+    }
+}
+impl Display for Index {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", AstPrinter.visit_index(self))
     }
 }
 
 pub struct Lit {
-    pub value: Option<Box<dyn AnyDebug>>,
+    pub id: ExprId,
+    pub value: Literal,
+}
+impl Expr for Lit {
+    fn node(&self) -> Node<'_> {
+        Node::Lit(self)
+    }
+
+    fn id(&self) -> ExprId {
+        self.id
+    }
 }
-impl Expr for Lit {}
 impl Display for Lit {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match &self.value {
-            Some(val) => write!(f, "{:?}", val),
-            None => write!(f, "nil"),
-        }
+        write!(f, "{}", AstPrinter.visit_lit(self))
+    }
+}
+
+/// A binary operator that may skip evaluating its right operand.
+pub struct Logical {
+    pub id: ExprId,
+    pub left: Box<dyn Expr>,
+    pub operator: Token,
+    pub right: Box<dyn Expr>,
+}
+impl Expr for Logical {
+    fn node(&self) -> Node<'_> {
+        Node::Logical(self)
+    }
+
+    fn id(&self) -> ExprId {
+        self.id
+    }
+}
+impl Display for Logical {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", AstPrinter.visit_logical(self))
+    }
+}
+
+pub struct MapLit {
+    pub id: ExprId,
+    pub brace: Token,
+    pub entries: Vec<(Box<dyn Expr>, Box<dyn Expr>)>,
+}
+impl Expr for MapLit {
+    fn node(&self) -> Node<'_> {
+        Node::MapLit(self)
+    }
+
+    fn id(&self) -> ExprId {
+        self.id
+    }
+}
+impl Display for MapLit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", AstPrinter.visit_map_lit(self))
+    }
+}
+
+/// Comma separated expressions, evaluated left to right to the value of the last.
+pub struct Seq {
+    pub id: ExprId,
+    pub exprs: Vec<Box<dyn Expr>>,
+}
+impl Expr for Seq {
+    fn node(&self) -> Node<'_> {
+        Node::Seq(self)
+    }
+
+    fn id(&self) -> ExprId {
+        self.id
+    }
+}
+impl Display for Seq {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", AstPrinter.visit_seq(self))
     }
 }
 
 pub struct Un {
+    pub id: ExprId,
     pub operator: Token,
     pub right: Box<dyn Expr>,
 }
-impl Expr for Un {}
+impl Expr for Un {
+    fn node(&self) -> Node<'_> {
+        Node::Un(self)
+    }
+
+    fn id(&self) -> ExprId {
+        self.id
+    }
+}
 impl Display for Un {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "({} {})", self.operator, self.right)
+        write!(f, "{}", AstPrinter.visit_un(self))
+    }
+}
+
+pub struct Var {
+    pub id: ExprId,
+    pub name: Token,
+}
+impl Expr for Var {
+    fn node(&self) -> Node<'_> {
+        Node::Var(self)
+    }
+
+    fn id(&self) -> ExprId {
+        self.id
+    }
+}
+impl Display for Var {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", AstPrinter.visit_var(self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::scanner::scan_tokens;
+    use crate::S;
+
+    struct NodeCounter;
+
+    impl Visitor<usize> for NodeCounter {
+        fn visit_array_lit(&mut self, expr: &ArrayLit) -> usize {
+            1 + expr.elements.iter().map(|e| e.accept(self)).sum::<usize>()
+        }
+
+        fn visit_bin(&mut self, expr: &Bin) -> usize {
+            1 + expr.left.accept(self) + expr.right.accept(self)
+        }
+
+        fn visit_call(&mut self, expr: &Call) -> usize {
+            let args: usize = expr.arguments.iter().map(|arg| arg.accept(self)).sum();
+            1 + expr.callee.accept(self) + args
+        }
+
+        fn visit_cond(&mut self, expr: &Cond) -> usize {
+            1 + expr.cond.accept(self) + expr.cons.accept(self) + expr.alt.accept(self)
+        }
+
+        fn visit_grp(&mut self, expr: &Grp) -> usize {
+            1 + expr.expression.accept(self)
+        }
+
+        fn visit_index(&mut self, expr: &Index) -> usize {
+            1 + expr.object.accept(self) + expr.index.accept(self)
+        }
+
+        fn visit_lit(&mut self, _expr: &Lit) -> usize {
+            1
+        }
+
+        fn visit_logical(&mut self, expr: &Logical) -> usize {
+            1 + expr.left.accept(self) + expr.right.accept(self)
+        }
+
+        fn visit_map_lit(&mut self, expr: &MapLit) -> usize {
+            let entries: usize = expr
+                .entries
+                .iter()
+                .map(|(key, value)| key.accept(self) + value.accept(self))
+                .sum();
+            1 + entries
+        }
+
+        fn visit_seq(&mut self, expr: &Seq) -> usize {
+            1 + expr.exprs.iter().map(|e| e.accept(self)).sum::<usize>()
+        }
+
+        fn visit_un(&mut self, expr: &Un) -> usize {
+            1 + expr.right.accept(self)
+        }
+
+        fn visit_var(&mut self, _expr: &Var) -> usize {
+            1
+        }
+    }
+
+    #[test]
+    fn test_counting_visitor() {
+        // (* (grp (+ 1 2)) (- 3)) and (? (== 1 1) 2 3)
+        let expr = parse(&scan_tokens(&S!("(1 + 2) * -3"), "<test>"), "<test>");
+        assert_eq!(7, expr.accept(&mut NodeCounter));
+        let expr = parse(&scan_tokens(&S!("1 == 1 ? 2 : 3"), "<test>"), "<test>");
+        assert_eq!(6, expr.accept(&mut NodeCounter));
+    }
+
+    #[test]
+    fn test_printer_visitor() {
+        let expr = parse(&scan_tokens(&S!("(1 + 2) * -3"), "<test>"), "<test>");
+        assert_eq!(expr.to_string(), expr.accept(&mut AstPrinter));
+    }
+
+    #[test]
+    fn test_printer_escapes_strings() {
+        let source = r#"["a\nb", "say \"hi\"\t\\", ""]"#;
+        let expr = parse(&scan_tokens(source, "<test>"), "<test>");
+        assert_eq!(r#"(list "a\nb" "say \"hi\"\t\\" "")"#, expr.to_string());
+    }
+
+    #[test]
+    fn test_source_printer() {
+        for (source, printed) in [
+            ("(1 + 2) * 3", "(1 + 2) * 3"),
+            ("1 + 2 * 3", "1 + 2 * 3"),
+            ("(1 * 2) + ((3))", "1 * 2 + 3"),
+            ("1 - (2 - 3)", "1 - (2 - 3)"),
+            ("(1 - 2) - 3", "1 - 2 - 3"),
+            ("-(-1) + !(a or b)", "- -1 + !(a or b)"),
+            (
+                "(a ? b : c) ? d : (e ? f : g)",
+                "(a ? b : c) ? d : e ? f : g",
+            ),
+            (
+                "f((1, 2), [(3)], {\"k\": (4 ?? 5)})",
+                "f((1, 2), [3], {\"k\": 4 ?? 5})",
+            ),
+            ("(f)(1)[(2)] div (x[0])", "f(1)[2] div x[0]"),
+        ] {
+            let expr = parse(&scan_tokens(source, "<test>"), "<test>");
+            assert_eq!(printed, SourcePrinter.print(expr.as_ref(), 0), "{}", source);
+        }
     }
 }
@@ -0,0 +1,36 @@
+use crate::token::Symbol;
+use std::collections::hash_map::HashMap;
+
+/// Maps each distinct identifier to a small id so repeated names share one
+/// allocation and compare by number.
+#[derive(Debug, Default)]
+pub struct Interner {
+    ids: HashMap<String, Symbol>,
+    names: Vec<String>,
+}
+
+impl Interner {
+    /// Returns the symbol for `name`, adding it on first sight.
+    pub fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(symbol) = self.ids.get(name) {
+            return *symbol;
+        }
+        let symbol = Symbol(self.names.len() as u32);
+        self.names.push(name.to_string());
+        self.ids.insert(name.to_string(), symbol);
+        symbol
+    }
+
+    /// Returns the name a symbol was interned from.
+    pub fn resolve(&self, symbol: Symbol) -> &str {
+        &self.names[symbol.0 as usize]
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
@@ -0,0 +1,1083 @@
+use crate::error_fmt::Error;
+use crate::expression::{
+    ArrayLit, Bin, Call, Cond, Expr, Grp, Index, Lit, Logical, MapLit, Seq, Un, Var, Visitor,
+};
+use crate::natives::{natives, Native};
+use crate::token::{format_number, Literal, Token, TokenType, MAX_EXACT_INTEGER};
+use crate::S;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::rc::Rc;
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    Str(String),
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<HashMap<String, Value>>>),
+    Native(Rc<Native>),
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(l), Value::Bool(r)) => l == r,
+            (Value::Number(l), Value::Number(r)) => l == r,
+            (Value::Str(l), Value::Str(r)) => l == r,
+            // Collections and functions are only ever equal to themselves
+            (Value::List(l), Value::List(r)) => Rc::ptr_eq(l, r),
+            (Value::Map(l), Value::Map(r)) => Rc::ptr_eq(l, r),
+            (Value::Native(l), Value::Native(r)) => Rc::ptr_eq(l, r),
+            _ => false,
+        }
+    }
+}
+
+impl Value {
+    /// Lox truthiness: `nil` and `false` are falsey, everything else is truthy.
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Bool(false))
+    }
+}
+
+impl From<&Literal> for Value {
+    fn from(literal: &Literal) -> Self {
+        match literal {
+            Literal::Number(val) => Value::Number(*val),
+            Literal::String(val) | Literal::Identifier(val) => Value::Str(val.clone()),
+            Literal::Bool(val) => Value::Bool(*val),
+            Literal::Nil => Value::Nil,
+        }
+    }
+}
+
+impl Value {
+    /// Formats the value the way it is printed, with every negative zero in
+    /// it, nested ones included, shown as `0` when `hide_negative_zero`.
+    fn format(&self, hide_negative_zero: bool) -> String {
+        match self {
+            Value::Nil => S!("nil"),
+            Value::Bool(val) => val.to_string(),
+            Value::Number(val) if hide_negative_zero && *val == 0.0 => S!("0"),
+            Value::Number(val) => format_number(*val),
+            Value::Str(val) => val.clone(),
+            Value::List(list) => {
+                let elements: Vec<String> = list
+                    .borrow()
+                    .iter()
+                    .map(|v| v.format(hide_negative_zero))
+                    .collect();
+                format!("[{}]", elements.join(", "))
+            }
+            Value::Map(map) => {
+                // Sorted so printing a map is deterministic
+                let map = map.borrow();
+                let mut keys: Vec<&String> = map.keys().collect();
+                keys.sort();
+                let entries: Vec<String> = keys
+                    .iter()
+                    .map(|key| format!("{}: {}", key, map[*key].format(hide_negative_zero)))
+                    .collect();
+                format!("{{{}}}", entries.join(", "))
+            }
+            Value::Native(native) => format!("{:?}", native),
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.format(false))
+    }
+}
+
+#[derive(Debug)]
+pub struct RuntimeError {
+    // Boxed to keep `Result<Value, RuntimeError>` small
+    pub token: Box<Token>,
+    pub message: String,
+}
+
+impl RuntimeError {
+    pub fn new(token: &Token, message: &str) -> Self {
+        RuntimeError {
+            token: Box::new(token.clone()),
+            message: S!(message),
+        }
+    }
+
+    /// Converts into a reportable `Error` located at the offending token.
+    pub fn to_error(&self, text: &str, source_name: &str) -> Error {
+        Error::new(
+            S!("Runtime Error: ") + &self.message,
+            S!(text),
+            S!(source_name),
+            self.token.line,
+            self.token.col,
+        )
+        .with_width(self.token.span.len())
+    }
+}
+
+type Eval = Result<Value, RuntimeError>;
+
+/// The longest string, in bytes, that repeating with `*` may build.
+const MAX_REPEAT_LEN: usize = 1 << 30;
+
+pub struct Interpreter {
+    /// How many evaluations enclose the current one, to indent the trace.
+    depth: usize,
+    globals: HashMap<String, Value>,
+    /// Print negative zero as `0` instead of the reference `-0`.
+    pub hide_negative_zero: bool,
+    /// One line per evaluated expression, when tracing.
+    trace: Option<Vec<String>>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        let globals = natives()
+            .into_iter()
+            .map(|native| (S!(native.name), Value::Native(Rc::new(native))))
+            .collect();
+        Interpreter {
+            depth: 0,
+            globals,
+            hide_negative_zero: false,
+            trace: None,
+        }
+    }
+}
+
+impl Interpreter {
+    /// Records every evaluation step, see `take_trace`.
+    pub fn with_trace(mut self) -> Self {
+        self.trace = Some(Vec::new());
+        self
+    }
+
+    /// Returns the steps traced so far, each expression on its own line
+    /// before those of its operands and indented by depth.
+    pub fn take_trace(&mut self) -> Vec<String> {
+        self.trace.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    pub fn evaluate(&mut self, expr: &dyn Expr) -> Eval {
+        let Some(trace) = self.trace.as_mut() else {
+            return expr.accept(self);
+        };
+        // Reserve the line so it comes before the operands' lines
+        let line = trace.len();
+        trace.push(String::new());
+
+        self.depth += 1;
+        let value = expr.accept(self);
+        self.depth -= 1;
+
+        let result = match &value {
+            Ok(value) => self.stringify(value),
+            Err(err) => format!("error: {}", err.message),
+        };
+        let step = format!("{}eval {} -> {}", "  ".repeat(self.depth), expr, result);
+        if let Some(trace) = self.trace.as_mut() {
+            trace[line] = step;
+        }
+        value
+    }
+
+    pub fn stringify(&self, value: &Value) -> String {
+        value.format(self.hide_negative_zero)
+    }
+
+    fn checked_index(bracket: &Token, index: f64, len: usize) -> Result<usize, RuntimeError> {
+        if index.fract() != 0.0 || index < 0.0 || index >= len as f64 {
+            return Err(RuntimeError::new(bracket, "Index out of range."));
+        }
+        Ok(index as usize)
+    }
+
+    /// Repeats `string` `count` times, for `*` between a string and a number.
+    fn repeat(operator: &Token, string: &str, count: f64) -> Eval {
+        if count < 0.0 || count.fract() != 0.0 {
+            return Err(RuntimeError::new(
+                operator,
+                "Repeat count must be a non-negative integer.",
+            ));
+        }
+        // Measured in floats first, a huge count would saturate as a usize
+        if string.len() as f64 * count > MAX_REPEAT_LEN as f64 {
+            return Err(RuntimeError::new(operator, "Repeated string is too long."));
+        }
+        Ok(Value::Str(string.repeat(count as usize)))
+    }
+
+    /// Bitwise operators work on integral numbers, done in `i64` and cast back.
+    /// Operands past 2^53 are rejected since their low bits are already lost.
+    fn integer_operands(
+        operator: &Token,
+        left: &Value,
+        right: &Value,
+    ) -> Result<(i64, i64), RuntimeError> {
+        let (l, r) = Self::number_operands(operator, left, right)?;
+        if l.fract() != 0.0 || r.fract() != 0.0 {
+            return Err(RuntimeError::new(operator, "Operands must be integers."));
+        }
+        if l.abs() > MAX_EXACT_INTEGER || r.abs() > MAX_EXACT_INTEGER {
+            return Err(RuntimeError::new(
+                operator,
+                "Operands must be integers in range.",
+            ));
+        }
+        Ok((l as i64, r as i64))
+    }
+
+    fn number_operands(
+        operator: &Token,
+        left: &Value,
+        right: &Value,
+    ) -> Result<(f64, f64), RuntimeError> {
+        match (left, right) {
+            (Value::Number(l), Value::Number(r)) => Ok((*l, *r)),
+            _ => Err(RuntimeError::new(operator, "Operands must be numbers.")),
+        }
+    }
+}
+
+impl Visitor<Eval> for Interpreter {
+    fn visit_array_lit(&mut self, expr: &ArrayLit) -> Eval {
+        let mut elements = Vec::new();
+        for element in &expr.elements {
+            elements.push(self.evaluate(element.as_ref())?);
+        }
+        Ok(Value::List(Rc::new(RefCell::new(elements))))
+    }
+
+    fn visit_bin(&mut self, expr: &Bin) -> Eval {
+        let left = self.evaluate(expr.left.as_ref())?;
+        let right = self.evaluate(expr.right.as_ref())?;
+        let op = &expr.operator;
+
+        match op.token_type {
+            TokenType::EqualEqual => Ok(Value::Bool(left == right)),
+            TokenType::BangEqual => Ok(Value::Bool(left != right)),
+            TokenType::Plus => match (left, right) {
+                (Value::Number(l), Value::Number(r)) => Ok(Value::Number(l + r)),
+                (Value::Str(l), Value::Str(r)) => Ok(Value::Str(l + &r)),
+                _ => Err(RuntimeError::new(
+                    op,
+                    "Operands must be two numbers or two strings.",
+                )),
+            },
+            TokenType::Star => match (&left, &right) {
+                (Value::Str(s), Value::Number(n)) | (Value::Number(n), Value::Str(s)) => {
+                    Self::repeat(op, s, *n)
+                }
+                _ => {
+                    let (l, r) = Self::number_operands(op, &left, &right)?;
+                    Ok(Value::Number(l * r))
+                }
+            },
+            TokenType::Greater
+            | TokenType::GreaterEqual
+            | TokenType::Less
+            | TokenType::LessEqual => {
+                // Strings compare lexicographically, NaN compares false with everything
+                let ordering = match (&left, &right) {
+                    (Value::Number(l), Value::Number(r)) => l.partial_cmp(r),
+                    (Value::Str(l), Value::Str(r)) => Some(l.cmp(r)),
+                    _ => {
+                        return Err(RuntimeError::new(
+                            op,
+                            "Operands must be two numbers or two strings.",
+                        ))
+                    }
+                };
+                Ok(Value::Bool(ordering.is_some_and(|o| match op.token_type {
+                    TokenType::Greater => o.is_gt(),
+                    TokenType::GreaterEqual => o.is_ge(),
+                    TokenType::Less => o.is_lt(),
+                    _ => o.is_le(),
+                })))
+            }
+            TokenType::Ampersand
+            | TokenType::Pipe
+            | TokenType::Caret
+            | TokenType::LessLess
+            | TokenType::GreaterGreater => {
+                let (l, r) = Self::integer_operands(op, &left, &right)?;
+                let result = match op.token_type {
+                    TokenType::Ampersand => l & r,
+                    TokenType::Pipe => l | r,
+                    TokenType::Caret => l ^ r,
+                    _ => {
+                        let shifted = match u32::try_from(r) {
+                            Ok(r) if op.token_type == TokenType::LessLess => l.checked_shl(r),
+                            Ok(r) => l.checked_shr(r),
+                            Err(_) => None,
+                        };
+                        shifted.ok_or_else(|| {
+                            RuntimeError::new(op, "Shift amount must be between 0 and 63.")
+                        })?
+                    }
+                };
+                Ok(Value::Number(result as f64))
+            }
+            _ => {
+                let (l, r) = Self::number_operands(op, &left, &right)?;
+                let divides = matches!(
+                    op.token_type,
+                    TokenType::Slash | TokenType::Div | TokenType::Percent
+                );
+                if divides && r == 0.0 {
+                    return Err(RuntimeError::new(op, "Division by zero."));
+                }
+                match op.token_type {
+                    TokenType::Minus => Ok(Value::Number(l - r)),
+                    TokenType::Slash => Ok(Value::Number(l / r)),
+                    TokenType::Div => Ok(Value::Number((l / r).floor())),
+                    // Floored like `div`, so the result takes the sign of `r`.
+                    // Adjusting the exact remainder avoids rounding `l / r`
+                    TokenType::Percent => {
+                        let m = l % r;
+                        match m != 0.0 && (m < 0.0) != (r < 0.0) {
+                            true => Ok(Value::Number(m + r)),
+                            false => Ok(Value::Number(m)),
+                        }
+                    }
+                    _ => Err(RuntimeError::new(op, "Unknown binary operator.")),
+                }
+            }
+        }
+    }
+
+    fn visit_call(&mut self, expr: &Call) -> Eval {
+        let callee = self.evaluate(expr.callee.as_ref())?;
+        let mut arguments = Vec::new();
+        for arg in &expr.arguments {
+            arguments.push(self.evaluate(arg.as_ref())?);
+        }
+
+        match callee {
+            Value::Native(native) => {
+                if !native.arity.accepts(arguments.len()) {
+                    return Err(RuntimeError::new(
+                        &expr.paren,
+                        &format!(
+                            "Expected {} arguments but got {}.",
+                            native.arity,
+                            arguments.len()
+                        ),
+                    ));
+                }
+                (native.func)(&arguments)
+                    .map_err(|message| RuntimeError::new(&expr.paren, &message))
+            }
+            _ => Err(RuntimeError::new(
+                &expr.paren,
+                "Can only call functions and classes.",
+            )),
+        }
+    }
+
+    fn visit_cond(&mut self, expr: &Cond) -> Eval {
+        if self.evaluate(expr.cond.as_ref())?.is_truthy() {
+            self.evaluate(expr.cons.as_ref())
+        } else {
+            self.evaluate(expr.alt.as_ref())
+        }
+    }
+
+    fn visit_grp(&mut self, expr: &Grp) -> Eval {
+        self.evaluate(expr.expression.as_ref())
+    }
+
+    fn visit_index(&mut self, expr: &Index) -> Eval {
+        let object = self.evaluate(expr.object.as_ref())?;
+        let index = self.evaluate(expr.index.as_ref())?;
+
+        match (object, index) {
+            (Value::List(list), Value::Number(i)) => {
+                let list = list.borrow();
+                let i = Self::checked_index(&expr.bracket, i, list.len())?;
+                Ok(list[i].clone())
+            }
+            (Value::List(_), _) => Err(RuntimeError::new(
+                &expr.bracket,
+                "List index must be a number.",
+            )),
+            // Strings index by character, not byte
+            (Value::Str(s), Value::Number(i)) => {
+                let i = Self::checked_index(&expr.bracket, i, s.chars().count())?;
+                Ok(Value::Str(s.chars().nth(i).unwrap().to_string()))
+            }
+            (Value::Str(_), _) => Err(RuntimeError::new(
+                &expr.bracket,
+                "String index must be a number.",
+            )),
+            // Looking up a missing key is not an error, it yields nil
+            (Value::Map(map), Value::Str(key)) => {
+                Ok(map.borrow().get(&key).cloned().unwrap_or(Value::Nil))
+            }
+            (Value::Map(_), _) => Err(RuntimeError::new(
+                &expr.bracket,
+                "Map key must be a string.",
+            )),
+            _ => Err(RuntimeError::new(
+                &expr.bracket,
+                "Only lists, maps and strings can be indexed.",
+            )),
+        }
+    }
+
+    fn visit_lit(&mut self, expr: &Lit) -> Eval {
+        Ok(Value::from(&expr.value))
+    }
+
+    fn visit_logical(&mut self, expr: &Logical) -> Eval {
+        let left = self.evaluate(expr.left.as_ref())?;
+
+        match expr.operator.token_type {
+            // Only a nil left side evaluates the right side
+            TokenType::QuestionQuestion => match left {
+                Value::Nil => self.evaluate(expr.right.as_ref()),
+                _ => Ok(left),
+            },
+            // Both return whichever operand decided the result, uncoerced
+            TokenType::Or => match left.is_truthy() {
+                true => Ok(left),
+                false => self.evaluate(expr.right.as_ref()),
+            },
+            TokenType::And => match left.is_truthy() {
+                true => self.evaluate(expr.right.as_ref()),
+                false => Ok(left),
+            },
+            _ => Err(RuntimeError::new(
+                &expr.operator,
+                "Unknown logical operator.",
+            )),
+        }
+    }
+
+    fn visit_map_lit(&mut self, expr: &MapLit) -> Eval {
+        let mut map = HashMap::new();
+        for (key, value) in &expr.entries {
+            let key = match self.evaluate(key.as_ref())? {
+                Value::Str(key) => key,
+                _ => return Err(RuntimeError::new(&expr.brace, "Map key must be a string.")),
+            };
+            map.insert(key, self.evaluate(value.as_ref())?);
+        }
+        Ok(Value::Map(Rc::new(RefCell::new(map))))
+    }
+
+    fn visit_seq(&mut self, expr: &Seq) -> Eval {
+        let mut value = Value::Nil;
+        for expr in &expr.exprs {
+            value = self.evaluate(expr.as_ref())?;
+        }
+        Ok(value)
+    }
+
+    fn visit_un(&mut self, expr: &Un) -> Eval {
+        let right = self.evaluate(expr.right.as_ref())?;
+
+        match expr.operator.token_type {
+            TokenType::Minus => match right {
+                Value::Number(val) => Ok(Value::Number(-val)),
+                _ => Err(RuntimeError::new(
+                    &expr.operator,
+                    "Operand must be a number.",
+                )),
+            },
+            TokenType::Bang => Ok(Value::Bool(!right.is_truthy())),
+            _ => Err(RuntimeError::new(&expr.operator, "Unknown unary operator.")),
+        }
+    }
+
+    fn visit_var(&mut self, expr: &Var) -> Eval {
+        match self.globals.get(&expr.name.lexeme) {
+            Some(value) => Ok(value.clone()),
+            None => Err(RuntimeError::new(
+                &expr.name,
+                &format!("Undefined variable '{}'.", expr.name.lexeme),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::scanner::scan_tokens;
+
+    fn eval(interpreter: &mut Interpreter, source: &str) -> Eval {
+        let expr = parse(&scan_tokens(source, "<test>"), "<test>");
+        interpreter.evaluate(expr.as_ref())
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let mut interpreter = Interpreter::default();
+        assert_eq!(
+            Value::Number(-3.0),
+            eval(&mut interpreter, "(1 + 2) * -1").unwrap()
+        );
+        assert_eq!(
+            Value::Str(S!("ab")),
+            eval(&mut interpreter, "\"a\" + \"b\"").unwrap()
+        );
+        assert!(eval(&mut interpreter, "1 + \"b\"").is_err());
+    }
+
+    #[test]
+    fn test_negative_zero() {
+        let mut interpreter = Interpreter::default();
+        let value = eval(&mut interpreter, "-0").unwrap();
+        assert_eq!("-0", interpreter.stringify(&value));
+
+        interpreter.hide_negative_zero = true;
+        assert_eq!("0", interpreter.stringify(&value));
+        assert_eq!("0", interpreter.stringify(&Value::Number(0.0)));
+        assert_eq!("-1", interpreter.stringify(&Value::Number(-1.0)));
+
+        let value = eval(&mut interpreter, "[-0, [1, -0], {\"a\": -0}]").unwrap();
+        assert_eq!("[0, [1, 0], {a: 0}]", interpreter.stringify(&value));
+        interpreter.hide_negative_zero = false;
+        assert_eq!("[-0, [1, -0], {a: -0}]", interpreter.stringify(&value));
+    }
+
+    #[test]
+    fn test_chr_ord() {
+        let mut interpreter = Interpreter::default();
+        assert_eq!(
+            Value::Number(65.0),
+            eval(&mut interpreter, "ord(\"A\")").unwrap()
+        );
+        assert_eq!(
+            Value::Str(S!("A")),
+            eval(&mut interpreter, "chr(65)").unwrap()
+        );
+        assert_eq!(
+            Value::Str(S!("😀")),
+            eval(&mut interpreter, "chr(128512)").unwrap()
+        );
+        assert_eq!(
+            "Invalid code point 55296.",
+            eval(&mut interpreter, "chr(55296)").unwrap_err().message
+        );
+        assert!(eval(&mut interpreter, "chr(1.5)").is_err());
+        assert!(eval(&mut interpreter, "ord(\"AB\")").is_err());
+        assert!(eval(&mut interpreter, "ord(\"\")").is_err());
+    }
+
+    #[test]
+    fn test_call_errors() {
+        let mut interpreter = Interpreter::default();
+        assert_eq!(
+            "Expected 1 arguments but got 2.",
+            eval(&mut interpreter, "chr(1, 2)").unwrap_err().message
+        );
+        assert_eq!(
+            "Can only call functions and classes.",
+            eval(&mut interpreter, "1(2)").unwrap_err().message
+        );
+        assert_eq!(
+            "Undefined variable 'nope'.",
+            eval(&mut interpreter, "nope").unwrap_err().message
+        );
+    }
+
+    #[test]
+    fn test_sequence() {
+        let mut interpreter = Interpreter::default();
+        assert_eq!(
+            Value::Number(3.0),
+            eval(&mut interpreter, "1, 2, 3").unwrap()
+        );
+        // Every operand is evaluated, so an error on the left still surfaces
+        assert!(eval(&mut interpreter, "-nil, 3").is_err());
+    }
+
+    #[test]
+    fn test_lists() {
+        let mut interpreter = Interpreter::default();
+        let list = eval(&mut interpreter, "[1, \"two\", [3]]").unwrap();
+        assert_eq!("[1, two, [3]]", list.to_string());
+        assert_eq!(
+            Value::Str(S!("two")),
+            eval(&mut interpreter, "[1, \"two\", [3]][1]").unwrap()
+        );
+        assert_eq!(
+            Value::Number(3.0),
+            eval(&mut interpreter, "[1, \"two\", [3]][2][0]").unwrap()
+        );
+
+        let err = eval(&mut interpreter, "[1, 2]\n[2]").unwrap_err();
+        assert_eq!("Index out of range.", err.message);
+        assert_eq!(TokenType::LeftBracket, err.token.token_type);
+        assert_eq!(2, err.token.line);
+        assert!(eval(&mut interpreter, "[1][-1]").is_err());
+        assert!(eval(&mut interpreter, "[1][0.5]").is_err());
+    }
+
+    #[test]
+    fn test_maps() {
+        let mut interpreter = Interpreter::default();
+        let map = eval(&mut interpreter, "{ \"b\": 2, \"a\": [1] }").unwrap();
+        assert_eq!("{a: [1], b: 2}", map.to_string());
+        assert_eq!(
+            Value::Number(2.0),
+            eval(&mut interpreter, "{ \"a\": 1, \"b\": 2 }[\"b\"]").unwrap()
+        );
+        assert_eq!(
+            Value::Nil,
+            eval(&mut interpreter, "{ \"a\": 1 }[\"c\"]").unwrap()
+        );
+        assert!(eval(&mut interpreter, "{ 1: 1 }").is_err());
+        assert!(eval(&mut interpreter, "{ \"a\": 1 }[1]").is_err());
+    }
+
+    #[test]
+    fn test_strings() {
+        let mut interpreter = Interpreter::default();
+        assert_eq!(
+            Value::Str(S!("b")),
+            eval(&mut interpreter, "\"abc\"[1]").unwrap()
+        );
+        assert_eq!(
+            Value::Str(S!("é")),
+            eval(&mut interpreter, "\"héllo\"[1]").unwrap()
+        );
+        assert_eq!(
+            Value::Number(3.0),
+            eval(&mut interpreter, "len(\"abc\")").unwrap()
+        );
+        assert_eq!(
+            Value::Number(2.0),
+            eval(&mut interpreter, "len([1, [2, 3]])").unwrap()
+        );
+        assert_eq!(
+            "Index out of range.",
+            eval(&mut interpreter, "\"abc\"[3]").unwrap_err().message
+        );
+        assert!(eval(&mut interpreter, "len(3)").is_err());
+    }
+
+    #[test]
+    fn test_coalesce() {
+        let mut interpreter = Interpreter::default();
+        assert_eq!(
+            Value::Number(2.0),
+            eval(&mut interpreter, "nil ?? 2").unwrap()
+        );
+        assert_eq!(
+            Value::Number(1.0),
+            eval(&mut interpreter, "1 ?? 2").unwrap()
+        );
+        assert_eq!(
+            Value::Bool(false),
+            eval(&mut interpreter, "false ?? 2").unwrap()
+        );
+        assert_eq!(
+            Value::Number(3.0),
+            eval(&mut interpreter, "nil ?? nil ?? 3").unwrap()
+        );
+        // The right side is only evaluated when the left is nil
+        assert_eq!(
+            Value::Number(1.0),
+            eval(&mut interpreter, "1 ?? -nil").unwrap()
+        );
+        assert!(eval(&mut interpreter, "nil ?? -nil").is_err());
+    }
+
+    #[test]
+    fn test_div() {
+        let mut interpreter = Interpreter::default();
+        assert_eq!(
+            Value::Bool(true),
+            eval(&mut interpreter, "7 div 2 == 3").unwrap()
+        );
+        assert_eq!(
+            Value::Number(-4.0),
+            eval(&mut interpreter, "-7 div 2").unwrap()
+        );
+        assert!(eval(&mut interpreter, "\"7\" div 2").is_err());
+    }
+
+    #[test]
+    fn test_bitwise() {
+        let mut interpreter = Interpreter::default();
+        assert_eq!(
+            Value::Bool(true),
+            eval(&mut interpreter, "6 & 3 == 2").unwrap()
+        );
+        assert_eq!(Value::Number(7.0), eval(&mut interpreter, "6 | 3").unwrap());
+        assert_eq!(Value::Number(5.0), eval(&mut interpreter, "6 ^ 3").unwrap());
+        assert_eq!(
+            Value::Number(24.0),
+            eval(&mut interpreter, "6 << 2").unwrap()
+        );
+        assert_eq!(
+            Value::Number(-2.0),
+            eval(&mut interpreter, "-6 >> 2").unwrap()
+        );
+        assert_eq!(
+            "Operands must be integers.",
+            eval(&mut interpreter, "6.5 & 3").unwrap_err().message
+        );
+        assert!(eval(&mut interpreter, "1 << -1").is_err());
+        assert!(eval(&mut interpreter, "\"6\" | 3").is_err());
+
+        // Large operands would otherwise saturate when cast to i64
+        for source in ["1e300 & 1", "1e20 | 0", "1 ^ -1e20"] {
+            assert_eq!(
+                "Operands must be integers in range.",
+                eval(&mut interpreter, source).unwrap_err().message,
+                "{}",
+                source
+            );
+        }
+        assert_eq!(
+            Value::Number(9007199254740992.0),
+            eval(&mut interpreter, "9007199254740992 | 0").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_unary() {
+        let mut interpreter = Interpreter::default();
+        let err = eval(&mut interpreter, "-\"a\"").unwrap_err();
+        assert_eq!("Operand must be a number.", err.message);
+        assert_eq!(TokenType::Minus, err.token.token_type);
+
+        assert_eq!(Value::Bool(true), eval(&mut interpreter, "!nil").unwrap());
+        assert_eq!(Value::Bool(false), eval(&mut interpreter, "!0").unwrap());
+        assert_eq!(
+            Value::Bool(true),
+            eval(&mut interpreter, "!!\"a\"").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_division_by_zero() {
+        let mut interpreter = Interpreter::default();
+        for source in ["1 / 0", "1 / -0", "5 div 0", "5 % 0", "1 / (2 - 2)"] {
+            let err = eval(&mut interpreter, source).unwrap_err();
+            assert_eq!("Division by zero.", err.message, "{}", source);
+        }
+        let err = eval(&mut interpreter, "1 +\n  5 div 0").unwrap_err();
+        assert_eq!((TokenType::Div, 2), (err.token.token_type, err.token.line));
+        assert_eq!(Value::Number(0.0), eval(&mut interpreter, "0 / 5").unwrap());
+    }
+
+    #[test]
+    fn test_double_minus() {
+        let mut interpreter = Interpreter::default();
+        assert_eq!(Value::Number(2.0), eval(&mut interpreter, "1--1").unwrap());
+        assert_eq!(Value::Number(1.0), eval(&mut interpreter, "--1").unwrap());
+    }
+
+    #[test]
+    fn test_modulo() {
+        let mut interpreter = Interpreter::default();
+        for (source, expected) in [
+            ("7 % 3", 1.0),
+            ("-7 % 3", 2.0),
+            ("7 % -3", -2.0),
+            ("7.5 % 2", 1.5),
+            ("1 + 8 % 3 * 2", 5.0),
+            ("1e17 % 3", 1.0),
+            ("-1e17 % 3", 2.0),
+            ("1e17 % -3", -2.0),
+            ("-6 % 3", 0.0),
+            ("5 % (1e308 * 10)", 5.0),
+        ] {
+            assert_eq!(
+                Value::Number(expected),
+                eval(&mut interpreter, source).unwrap(),
+                "{}",
+                source
+            );
+        }
+        assert!(eval(&mut interpreter, "\"7\" % 2").is_err());
+    }
+
+    #[test]
+    fn test_comparisons() {
+        let mut interpreter = Interpreter::default();
+        for (source, expected) in [
+            ("\"a\" < \"b\"", true),
+            ("\"b\" < \"a\"", false),
+            ("\"ab\" > \"a\"", true),
+            ("\"a\" <= \"a\"", true),
+            ("\"B\" >= \"a\"", false),
+            ("2 >= 2", true),
+            ("1 > 2", false),
+        ] {
+            assert_eq!(
+                Value::Bool(expected),
+                eval(&mut interpreter, source).unwrap(),
+                "{}",
+                source
+            );
+        }
+        let err = eval(&mut interpreter, "1 < \"a\"").unwrap_err();
+        assert_eq!("Operands must be two numbers or two strings.", err.message);
+    }
+
+    #[test]
+    fn test_and_or() {
+        let mut interpreter = Interpreter::default();
+        for (source, expected) in [
+            // The right operands would be runtime errors if evaluated
+            ("false and missing()", Value::Bool(false)),
+            ("nil and 1 / 0", Value::Nil),
+            ("true or missing()", Value::Bool(true)),
+            ("\"yes\" or 1 / 0", Value::Str(S!("yes"))),
+            ("nil or false or 3 or 4", Value::Number(3.0)),
+            ("1 and \"two\"", Value::Str(S!("two"))),
+            ("false or nil", Value::Nil),
+            // `and` binds tighter than `or`, and both looser than equality
+            ("true or false and false", Value::Bool(true)),
+            ("1 == 2 or 3 == 3", Value::Bool(true)),
+        ] {
+            assert_eq!(
+                expected,
+                eval(&mut interpreter, source).unwrap(),
+                "{}",
+                source
+            );
+        }
+
+        let err = eval(&mut interpreter, "true and missing").unwrap_err();
+        assert_eq!("Undefined variable 'missing'.", err.message);
+    }
+
+    #[test]
+    fn test_comparisons_print_bools() {
+        let mut interpreter = Interpreter::default();
+        for (source, printed) in [
+            ("1 < 2", "true"),
+            ("nil == nil", "true"),
+            ("2 <= 1", "false"),
+            ("\"a\" != \"a\"", "false"),
+            ("nil == false", "false"),
+        ] {
+            let value = eval(&mut interpreter, source).unwrap();
+            assert!(matches!(value, Value::Bool(_)), "{}", source);
+            assert_eq!(printed, interpreter.stringify(&value), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_push_pop_calls() {
+        let mut interpreter = Interpreter::default();
+        for (source, expected) in [
+            ("push([1, 2], 3)", Value::Number(3.0)),
+            ("pop([1, 2])", Value::Number(2.0)),
+            ("pop([])", Value::Nil),
+        ] {
+            assert_eq!(
+                expected,
+                eval(&mut interpreter, source).unwrap(),
+                "{}",
+                source
+            );
+        }
+        let err = eval(&mut interpreter, "push([])").unwrap_err();
+        assert_eq!("Expected 2 arguments but got 1.", err.message);
+        let err = eval(&mut interpreter, "pop(1)").unwrap_err();
+        assert_eq!("Argument to 'pop' must be a list.", err.message);
+    }
+
+    #[test]
+    fn test_range() {
+        let mut interpreter = Interpreter::default();
+        for (source, printed) in [
+            ("range(3)", "[0, 1, 2]"),
+            ("range(2, 5)", "[2, 3, 4]"),
+            ("range(-2, 1)", "[-2, -1, 0]"),
+            ("range(0)", "[]"),
+            ("range(5, 2)", "[]"),
+        ] {
+            let value = eval(&mut interpreter, source).unwrap();
+            assert_eq!(printed, value.to_string(), "{}", source);
+        }
+
+        for (source, message) in [
+            ("range(1.5)", "Arguments to 'range' must be integers."),
+            ("range(0, \"3\")", "Arguments to 'range' must be integers."),
+            ("range(1, 2, 3)", "Expected 1 or 2 arguments but got 3."),
+            ("range(9007199254740992)", "Range is too large."),
+            ("range(-9007199254740992, 0)", "Range is too large."),
+        ] {
+            let err = eval(&mut interpreter, source).unwrap_err();
+            assert_eq!(message, err.message, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_string_repeat() {
+        let mut interpreter = Interpreter::default();
+        for (source, expected) in [
+            ("\"ab\" * 3", "ababab"),
+            ("2 * \"é\"", "éé"),
+            ("\"x\" * 0", ""),
+            ("\"\" * 100000000000000000000000", ""),
+        ] {
+            assert_eq!(
+                Value::Str(S!(expected)),
+                eval(&mut interpreter, source).unwrap(),
+                "{}",
+                source
+            );
+        }
+
+        for (source, message) in [
+            (
+                "\"ab\" * -1",
+                "Repeat count must be a non-negative integer.",
+            ),
+            (
+                "\"ab\" * 1.5",
+                "Repeat count must be a non-negative integer.",
+            ),
+            (
+                "\"ab\" * 100000000000000000000000",
+                "Repeated string is too long.",
+            ),
+            ("\"ab\" * 1e15", "Repeated string is too long."),
+            ("\"ab\" * 1e30", "Repeated string is too long."),
+            ("\"abcd\" * 268435457", "Repeated string is too long."),
+            ("\"ab\" * \"c\"", "Operands must be numbers."),
+        ] {
+            let err = eval(&mut interpreter, source).unwrap_err();
+            assert_eq!(message, err.message, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_type() {
+        let mut interpreter = Interpreter::default();
+        for (source, name) in [
+            ("type(1.5)", "number"),
+            ("type(\"a\")", "string"),
+            ("type(true)", "bool"),
+            ("type(nil)", "nil"),
+            ("type(len)", "function"),
+            ("type([1])", "list"),
+            ("type({})", "map"),
+            ("type(type(1))", "string"),
+        ] {
+            assert_eq!(
+                Value::Str(S!(name)),
+                eval(&mut interpreter, source).unwrap()
+            );
+        }
+        assert!(eval(&mut interpreter, "type()").is_err());
+    }
+
+    #[test]
+    fn test_num_str() {
+        let mut interpreter = Interpreter::default();
+        for (source, expected) in [
+            ("num(\"3.5\")", Value::Number(3.5)),
+            ("num(\" -2 \")", Value::Number(-2.0)),
+            ("num(4)", Value::Number(4.0)),
+            ("num(\"abc\")", Value::Nil),
+            ("num(\"\")", Value::Nil),
+            ("num(\"inf\")", Value::Nil),
+            ("str(42)", Value::Str(S!("42"))),
+            ("str(3.25)", Value::Str(S!("3.25"))),
+            ("str(true)", Value::Str(S!("true"))),
+            ("str(nil)", Value::Str(S!("nil"))),
+            ("str([1, \"a\"])", Value::Str(S!("[1, a]"))),
+        ] {
+            assert_eq!(
+                expected,
+                eval(&mut interpreter, source).unwrap(),
+                "{}",
+                source
+            );
+        }
+        assert!(eval(&mut interpreter, "num(nil)").is_err());
+    }
+
+    #[test]
+    fn test_format() {
+        let mut interpreter = Interpreter::default();
+        assert_eq!(
+            Value::Str(S!("1 + 2 = 3")),
+            eval(&mut interpreter, "format(\"{} + {} = {}\", 1, 2, 1 + 2)").unwrap()
+        );
+        assert_eq!(
+            Value::Str(S!("plain")),
+            eval(&mut interpreter, "format(\"plain\")").unwrap()
+        );
+        assert_eq!(
+            "Format string has 2 placeholders but got 1 arguments.",
+            eval(&mut interpreter, "format(\"{}{}\", 1)")
+                .unwrap_err()
+                .message
+        );
+        assert_eq!(
+            "Expected at least 1 arguments but got 0.",
+            eval(&mut interpreter, "format()").unwrap_err().message
+        );
+    }
+
+    #[test]
+    fn test_format_number() {
+        for (n, text) in [
+            (3.0, "3"),
+            (-12.0, "-12"),
+            (0.5, "0.5"),
+            (0.1 + 0.2, "0.30000000000000004"),
+            (-0.0, "-0"),
+            (1e20, "1e20"),
+            (1e21, "1e21"),
+            (9007199254740992.0, "9007199254740992"),
+            (-9007199254740992.0, "-9007199254740992"),
+            (9007199254740994.0, "9.007199254740994e15"),
+            (123456789012345680000.0, "1.2345678901234568e20"),
+            (-2.5e300, "-2.5e300"),
+            (0.000001, "0.000001"),
+            (1.5e-7, "1.5e-7"),
+        ] {
+            assert_eq!(text, format_number(n));
+            assert_eq!(text, Value::Number(n).to_string());
+            assert_eq!(text, Literal::Number(n).to_string());
+        }
+    }
+
+    #[test]
+    fn test_trace() {
+        let tokens = scan_tokens("(1 + 2) * -x", "<test>");
+        let expr = parse(&tokens, "<test>");
+        let mut interpreter = Interpreter::default().with_trace();
+        assert!(interpreter.evaluate(expr.as_ref()).is_err());
+        assert_eq!(
+            vec![
+                "eval (* (grp (+ 1 2)) (- x)) -> error: Undefined variable 'x'.",
+                "  eval (grp (+ 1 2)) -> 3",
+                "    eval (+ 1 2) -> 3",
+                "      eval 1 -> 1",
+                "      eval 2 -> 2",
+                "  eval (- x) -> error: Undefined variable 'x'.",
+                "    eval x -> error: Undefined variable 'x'.",
+            ],
+            interpreter.take_trace()
+        );
+        assert!(interpreter.take_trace().is_empty());
+
+        // Without tracing nothing is recorded
+        let mut interpreter = Interpreter::default();
+        interpreter.evaluate(expr.as_ref()).unwrap_err();
+        assert!(interpreter.take_trace().is_empty());
+    }
+}
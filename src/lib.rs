@@ -0,0 +1,211 @@
+use error_fmt::{format_errors, report_errors_json, Error};
+use expression::SourcePrinter;
+use interpreter::{Interpreter, Value};
+use std::fmt::Display;
+use std::time::{Duration, Instant};
+use token::{format_tokens, format_tokens_highlighted};
+
+pub mod error_fmt;
+pub mod expression;
+pub mod interner;
+pub mod interpreter;
+pub mod marcher;
+pub mod natives;
+pub mod parser;
+pub mod repl;
+pub mod scanner;
+pub mod token;
+mod utils;
+
+/// Command line flags shared by the prompt and file runners.
+#[derive(Default)]
+pub struct Options {
+    /// Print the scanned tokens and the parsed tree before evaluating.
+    pub verbose: bool,
+    /// Only scan and parse, reporting errors without evaluating.
+    pub check: bool,
+    /// Print diagnostics as JSON instead of annotated source.
+    pub json: bool,
+    /// Print how long each phase took to stderr.
+    pub time: bool,
+    /// Print each evaluation step and its value to stderr.
+    pub trace: bool,
+    /// Print the scanned tokens colored by category.
+    pub highlight: bool,
+    /// Stop printing diagnostics after this many, summarizing the rest.
+    pub max_errors: Option<usize>,
+}
+
+/// How long each phase of a run took.
+#[derive(Debug, Default)]
+pub struct Timings {
+    pub scan: Duration,
+    pub parse: Duration,
+    pub interpret: Duration,
+}
+
+impl Display for Timings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "scan:      {:?}", self.scan)?;
+        writeln!(f, "parse:     {:?}", self.parse)?;
+        write!(f, "interpret: {:?}", self.interpret)
+    }
+}
+
+pub fn run(source: String, source_name: &str, options: &Options) {
+    let mut interpreter = Interpreter::default();
+    if options.trace {
+        interpreter = interpreter.with_trace();
+    }
+    let mut timings = Timings::default();
+    let result = interpret(
+        &source,
+        source_name,
+        &mut interpreter,
+        options,
+        &mut timings,
+    );
+    for step in interpreter.take_trace() {
+        eprintln!("{}", step);
+    }
+    match result {
+        Ok(value) => println!("{}", interpreter.stringify(&value)),
+        Err(errors) => report(&errors, options),
+    }
+    if options.time {
+        eprintln!("{}", timings);
+    }
+}
+
+/// Prints the errors in the format the options ask for.
+pub fn report(errors: &[Error], options: &Options) {
+    match options.json {
+        true => report_errors_json(errors),
+        false => print!("{}", format_errors(errors, options.max_errors)),
+    }
+}
+
+/// Scans and parses `source` without evaluating it, returning every error.
+pub fn check(source: &str, source_name: &str) -> Vec<Error> {
+    match scanner::try_scan_tokens(source, source_name) {
+        Ok(tokens) => parser::try_parse(&tokens, source, source_name)
+            .err()
+            .unwrap_or_default(),
+        Err(errors) => errors,
+    }
+}
+
+/// Whether output may be colored, which users opt out of by setting
+/// `NO_COLOR` as described at https://no-color.org.
+pub fn color_enabled() -> bool {
+    std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+}
+
+/// Reprints `source` in canonical form, with single spaces around binary
+/// operators and only the parentheses precedence needs. Comments are not
+/// kept.
+pub fn format(source: &str, source_name: &str) -> Result<String, Vec<Error>> {
+    let tokens = scanner::try_scan_tokens(source, source_name)?;
+    let expr = parser::try_parse(&tokens, source, source_name)?;
+    Ok(format!("{}\n", SourcePrinter.print(expr.as_ref(), 0)))
+}
+
+/// Scans, parses and evaluates `source`, returning the value or the errors
+/// that stopped it without printing anything.
+pub fn run_string(source: &str) -> Result<Value, Vec<Error>> {
+    run_timed(source).0
+}
+
+/// Like `run_string`, also measuring each phase. Phases after an error
+/// keep a zero duration.
+pub fn run_timed(source: &str) -> (Result<Value, Vec<Error>>, Timings) {
+    let mut timings = Timings::default();
+    let result = interpret(
+        source,
+        "<string>",
+        &mut Interpreter::default(),
+        &Options::default(),
+        &mut timings,
+    );
+    (result, timings)
+}
+
+fn interpret(
+    source: &str,
+    source_name: &str,
+    interpreter: &mut Interpreter,
+    options: &Options,
+    timings: &mut Timings,
+) -> Result<Value, Vec<Error>> {
+    // Scanning phase
+    let start = Instant::now();
+    let tokens = scanner::try_scan_tokens(source, source_name);
+    timings.scan = start.elapsed();
+    let tokens = tokens?;
+    if options.highlight && color_enabled() {
+        print!("{}", format_tokens_highlighted(&tokens));
+    } else if options.highlight || options.verbose {
+        print!("{}", format_tokens(&tokens));
+    }
+    let start = Instant::now();
+    let expr = parser::try_parse(&tokens, source, source_name);
+    timings.parse = start.elapsed();
+    let expr = expr?;
+    if options.verbose {
+        println!("{}", expr);
+    }
+
+    // Evaluation phase
+    let start = Instant::now();
+    let value = interpreter.evaluate(expr.as_ref());
+    timings.interpret = start.elapsed();
+    value.map_err(|err| {
+        let line = source.lines().nth(err.token.line - 1).unwrap_or_default();
+        vec![err.to_error(line, source_name)]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_string() {
+        assert_eq!(Value::Number(3.0), run_string("1 + 2").unwrap());
+
+        let errors = run_string("1 +").unwrap_err();
+        assert_eq!(
+            "Syntax Error: Invalid token to start an expression.",
+            errors[0].message
+        );
+
+        let errors = run_string("1 +\n-\"a\"").unwrap_err();
+        assert_eq!(
+            "Runtime Error: Operand must be a number.",
+            errors[0].message
+        );
+        assert_eq!("-\"a\"", errors[0].text);
+    }
+
+    #[test]
+    fn test_format() {
+        let formatted = format("(1+2)*3 -(( 4 ))", "<test>").unwrap();
+        assert_eq!("(1 + 2) * 3 - 4\n", formatted);
+        assert_eq!(formatted, format(&formatted, "<test>").unwrap());
+        assert!(format("1 +", "<test>").is_err());
+    }
+
+    #[test]
+    fn test_run_timed() {
+        let elements = vec!["1 + 2 * 3"; 100].join(", ");
+        let source = format!("[{}][99] + len(\"abc\")", elements);
+        let (result, timings) = run_timed(&source);
+        assert_eq!(Value::Number(10.0), result.unwrap());
+        assert!(timings.scan > Duration::ZERO);
+        assert!(timings.parse > Duration::ZERO);
+        assert!(timings.interpret > Duration::ZERO);
+
+        let (_, timings) = run_timed("1 +");
+        assert_eq!(Duration::ZERO, timings.interpret);
+    }
+}
@@ -1,18 +1,19 @@
 use std::env;
 use std::fs;
-use std::io::{stdin, stdout, Write};
+use std::io::{self, stdin, Read};
 
-mod error_fmt;
-mod expression;
-mod marcher;
-mod parser;
-mod scanner;
-mod token;
-mod utils;
+use rlox::scanner::highlight;
+use rlox::{check, color_enabled, format, repl, report, run, Options};
 
-use parser::parse;
-//use scanner::Scanner;
-//use token::Token;
+/// Exit statuses from sysexits: bad arguments, bad input and unreadable input.
+const EXIT_USAGE: i32 = 64;
+const EXIT_DATA_ERR: i32 = 65;
+const EXIT_NO_INPUT: i32 = 66;
+
+const USAGE: &str =
+    "Usage: rlox [-v | --verbose] [--check] [--json] [--time] [--trace] [--highlight] [--max-errors N] [path | -]
+       rlox fmt [--write] [path | -]
+       rlox highlight [path | -]";
 
 fn main() {
     let args: Vec<String> = env::args().collect();
@@ -20,41 +21,171 @@ fn main() {
 }
 
 pub fn parse_args(args: Vec<String>) {
-    match args.len() {
-        1 => run_prompt(),
-        2 => run_file(args.get(1).unwrap()),
-        _ => {
-            println!("Too many args");
-            std::process::exit(-1)
+    match args.get(1).map(String::as_str) {
+        Some("fmt") => return fmt_args(args),
+        Some("highlight") => return highlight_args(args),
+        _ => {}
+    }
+    let mut options = Options::default();
+    let mut paths = Vec::new();
+    let mut args = args.into_iter().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--max-errors" => match args.next().and_then(|n| n.parse().ok()) {
+                Some(max) => options.max_errors = Some(max),
+                None => usage_error("Expected a count after --max-errors."),
+            },
+            "-v" | "--verbose" => options.verbose = true,
+            "--check" => options.check = true,
+            "--json" => options.json = true,
+            "--time" => options.time = true,
+            "--trace" => options.trace = true,
+            "--highlight" => options.highlight = true,
+            _ => paths.push(arg),
+        }
+    }
+
+    match paths.len() {
+        0 => repl::run_prompt(&options),
+        1 => run_file(&paths[0], &options),
+        _ => usage_error(&format!(
+            "Expected at most one path but got {}.",
+            paths.len()
+        )),
+    }
+}
+
+/// Handles `rlox fmt`, which prints the formatted source or, with
+/// `--write`, replaces the file with it.
+fn fmt_args(args: Vec<String>) {
+    let mut write = false;
+    let mut paths = Vec::new();
+    for arg in args.into_iter().skip(2) {
+        match arg.as_str() {
+            "--write" => write = true,
+            _ => paths.push(arg),
         }
     }
+    let input = match paths.as_slice() {
+        [] => Input::Stdin,
+        [path] => Input::from_path(path),
+        _ => usage_error(&format!(
+            "Expected at most one path but got {}.",
+            paths.len()
+        )),
+    };
+    if write && input == Input::Stdin {
+        usage_error("Cannot --write to stdin.");
+    }
+    let source = read_or_exit(&input);
+    let formatted = match format(&source, input.name()) {
+        Ok(formatted) => formatted,
+        Err(errors) => {
+            report(&errors, &Options::default());
+            std::process::exit(EXIT_DATA_ERR)
+        }
+    };
+    match input {
+        Input::File(path) if write => {
+            if let Err(err) = fs::write(path, formatted) {
+                eprintln!("Usage Error: Could not write '{}': {}", path, err);
+                std::process::exit(EXIT_NO_INPUT)
+            }
+        }
+        _ => print!("{}", formatted),
+    }
+}
+
+/// Handles `rlox highlight`, which prints the source colored by token.
+fn highlight_args(args: Vec<String>) {
+    let input = match &args[2..] {
+        [] => Input::Stdin,
+        [path] => Input::from_path(path),
+        paths => usage_error(&format!(
+            "Expected at most one path but got {}.",
+            paths.len()
+        )),
+    };
+    let source = read_or_exit(&input);
+    match color_enabled() {
+        true => print!("{}", highlight(&source)),
+        false => print!("{}", source),
+    }
 }
 
-fn run(source: String) {
-    // Scanning phase
-    let tokens = scanner::scan_tokens(&source);
-    println!("{:?}", tokens);
-    let expr = parser::parse(&tokens);
-    println!("{}", expr)
+/// Reports a problem with how rlox was invoked and exits.
+fn usage_error(message: &str) -> ! {
+    eprintln!("Usage Error: {}\n{}", message, USAGE);
+    std::process::exit(EXIT_USAGE)
 }
 
-fn run_prompt() {
-    let input = &mut String::new();
-    loop {
-        print!("> ");
-        Write::flush(&mut stdout()).expect("Flush failed!");
-        input.clear();
-        let _ = stdin().read_line(input);
-        run(input.to_string());
+/// Where the source of a program is read from.
+#[derive(Debug, PartialEq)]
+enum Input<'a> {
+    Stdin,
+    File(&'a str),
+}
+
+impl Input<'_> {
+    /// A path of `-` reads from stdin, as is usual for command line tools.
+    fn from_path(path: &str) -> Input<'_> {
+        match path {
+            "-" => Input::Stdin,
+            path => Input::File(path),
+        }
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            Input::Stdin => "<stdin>",
+            Input::File(path) => path,
+        }
     }
+
+    fn read(&self) -> io::Result<String> {
+        match self {
+            Input::Stdin => {
+                let mut source = String::new();
+                stdin().read_to_string(&mut source)?;
+                Ok(source)
+            }
+            Input::File(path) => fs::read_to_string(path),
+        }
+    }
+}
+
+/// Reads the whole input, exiting if it can't be read.
+fn read_or_exit(input: &Input) -> String {
+    input.read().unwrap_or_else(|err| {
+        eprintln!("Usage Error: Could not read '{}': {}", input.name(), err);
+        std::process::exit(EXIT_NO_INPUT)
+    })
 }
 
-fn run_file(path: &str) {
-    match fs::read_to_string(path) {
-        Ok(s) => run(s),
-        Err(err) => {
-            println!("{}", err.to_string());
-            std::process::exit(-1)
+fn run_file(path: &str, options: &Options) {
+    let input = Input::from_path(path);
+    let path = input.name();
+    let source = read_or_exit(&input);
+    if options.check {
+        let errors = check(&source, path);
+        report(&errors, options);
+        if !errors.is_empty() {
+            std::process::exit(EXIT_DATA_ERR)
         }
+    } else {
+        run(source, path, options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_input_from_path() {
+        assert_eq!(Input::Stdin, Input::from_path("-"));
+        assert_eq!("<stdin>", Input::from_path("-").name());
+        assert_eq!(Input::File("a.lox"), Input::from_path("a.lox"));
+        assert_eq!(Input::File("./-"), Input::from_path("./-"));
     }
 }
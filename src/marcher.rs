@@ -1,6 +1,4 @@
-use std::collections::hash_map::HashMap;
-use std::fmt::Debug;
-use std::hash::Hash;
+use std::fmt::{Debug, Display};
 
 use std::ops::Range;
 
@@ -20,7 +18,6 @@ pub struct Marcher<T> {
     pub curr: usize,
 }
 
-#[allow(dead_code)]
 impl<T> Marcher<T>
 where
     T: PartialEq + Debug + Clone,
@@ -39,8 +36,10 @@ where
     ///
     /// # Example
     /// ```rust
-    ///let mut m: Marcher<i32> = Marcher::new(vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10]);
-    ///assert!(1, m.advance(1).unwrap());
+    /// use rlox::marcher::Marcher;
+    ///
+    /// let mut m: Marcher<i32> = Marcher::new(vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10]);
+    /// assert_eq!(Some(&1), m.advance(1));
     /// ```
     pub fn advance(&mut self, offset: usize) -> Option<&T> {
         let (val, _ov) = self.curr.overflowing_add(offset);
@@ -50,6 +49,20 @@ where
         t
     }
 
+    /// Like `advance`, but leaves the marcher in place and returns an error
+    /// when the offset would move it past the last element.
+    pub fn try_advance(&mut self, offset: usize) -> Result<&T, MarcherError> {
+        let pos = self.curr.wrapping_add(offset);
+        if pos >= self.values.len() {
+            return Err(MarcherError::OutOfBounds {
+                pos,
+                len: self.values.len(),
+            });
+        }
+        self.curr = pos;
+        Ok(&self.values[pos])
+    }
+
     pub fn peek(&self, offset: isize) -> Option<&T> {
         let add = offset >= 0;
         let pos = match add {
@@ -58,7 +71,7 @@ where
                 val
             }
             false => {
-                let (val, _) = self.curr.overflowing_sub(offset.abs() as usize);
+                let (val, _) = self.curr.overflowing_sub(offset.unsigned_abs());
                 val
             }
         };
@@ -69,6 +82,15 @@ where
         self.values.get(range)
     }
 
+    /// Returns the upcoming elements that satisfy the predicate, stopping at
+    /// the first that does not, without moving the marcher.
+    pub fn peek_while(&self, mut predicate: impl FnMut(&T) -> bool) -> &[T] {
+        let start = self.curr.wrapping_add(1).min(self.values.len());
+        let rest = &self.values[start..];
+        let len = rest.iter().take_while(|t| predicate(t)).count();
+        &rest[..len]
+    }
+
     pub fn advance_until(
         &mut self,
         mut predicate: impl FnMut(&mut Marcher<T>, &T) -> bool,
@@ -97,12 +119,38 @@ where
         }
     }
 
+    /// Advances past the next element if it equals `expected`.
+    ///
+    /// `Token` equality compares every field, lexeme and position included,
+    /// so matching a token by its type alone should go through `advance_if`
+    /// comparing `token_type`.
+    pub fn advance_if_eq(&mut self, expected: &T) -> Option<&T> {
+        self.advance_if(|t| t == expected)
+    }
+
     pub fn completed(&self) -> bool {
-        let (len, _) = self.curr.overflowing_add(1 as usize);
+        let (len, _) = self.curr.overflowing_add(1);
         self.values.len() == len
     }
 }
 
+/// Errors from the checked `Marcher` methods.
+#[derive(Debug, PartialEq)]
+pub enum MarcherError {
+    /// Advancing would have moved to `pos`, past a vector of `len` elements.
+    OutOfBounds { pos: usize, len: usize },
+}
+
+impl Display for MarcherError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MarcherError::OutOfBounds { pos, len } => {
+                write!(f, "Cannot advance to {} in {} elements.", pos, len)
+            }
+        }
+    }
+}
+
 impl<T> Default for Marcher<T> {
     fn default() -> Self {
         Marcher {
@@ -111,3 +159,53 @@ impl<T> Default for Marcher<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_advance_if_eq() {
+        let mut m = Marcher::new(vec![1, 2, 3]);
+        assert_eq!(None, m.advance_if_eq(&2));
+        assert_eq!(Some(&1), m.advance_if_eq(&1));
+        assert_eq!(Some(&2), m.advance_if_eq(&2));
+        assert_eq!(None, m.advance_if_eq(&2));
+        assert_eq!(Some(&3), m.peek(1));
+    }
+
+    #[test]
+    fn test_peek_while() {
+        let mut m = Marcher::new(vec![1, 2, 3, 10, 4]);
+        assert_eq!(&[1, 2, 3], m.peek_while(|n| *n < 5));
+        assert_eq!(usize::MAX, m.curr);
+
+        m.advance(2);
+        assert_eq!(&[3], m.peek_while(|n| *n > 2 && *n < 10));
+        assert_eq!(1, m.curr);
+        assert!(m.peek_while(|n| *n == 0).is_empty());
+
+        m.advance(3);
+        assert!(m.peek_while(|_| true).is_empty());
+    }
+
+    #[test]
+    fn test_try_advance() {
+        let mut m = Marcher::new(vec![1, 2, 3]);
+        assert_eq!(Ok(&1), m.try_advance(1));
+        assert_eq!(Ok(&3), m.try_advance(2));
+        assert_eq!(Ok(&3), m.try_advance(0));
+
+        assert_eq!(
+            Err(MarcherError::OutOfBounds { pos: 3, len: 3 }),
+            m.try_advance(1)
+        );
+        // A failed advance leaves the marcher where it was
+        assert_eq!(2, m.curr);
+        assert!(m.completed());
+
+        let mut empty: Marcher<i32> = Marcher::default();
+        assert!(empty.try_advance(1).is_err());
+        assert_eq!(usize::MAX, empty.curr);
+    }
+}
@@ -0,0 +1,380 @@
+use crate::interpreter::Value;
+use crate::token::MAX_EXACT_INTEGER;
+use crate::S;
+use std::cell::RefCell;
+use std::fmt::{Debug, Display};
+use std::io::{self, stdin, stdout, BufRead, Write};
+use std::rc::Rc;
+
+pub type NativeFn = fn(&[Value]) -> Result<Value, String>;
+
+/// A builtin function implemented in Rust and bound in the global environment.
+pub struct Native {
+    pub name: &'static str,
+    pub arity: Arity,
+    pub func: NativeFn,
+}
+
+/// How many arguments a native accepts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arity {
+    Fixed(usize),
+    /// The given number or more, the rest are passed along in the slice.
+    AtLeast(usize),
+}
+
+impl Arity {
+    pub fn accepts(&self, count: usize) -> bool {
+        match self {
+            Arity::Fixed(arity) => count == *arity,
+            Arity::AtLeast(arity) => count >= *arity,
+        }
+    }
+}
+
+impl Display for Arity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Arity::Fixed(arity) => write!(f, "{}", arity),
+            Arity::AtLeast(arity) => write!(f, "at least {}", arity),
+        }
+    }
+}
+
+impl Debug for Native {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+pub fn natives() -> Vec<Native> {
+    #[cfg_attr(not(feature = "std-io"), allow(unused_mut))]
+    let mut natives = vec![
+        Native {
+            name: "chr",
+            arity: Arity::Fixed(1),
+            func: chr,
+        },
+        Native {
+            name: "format",
+            arity: Arity::AtLeast(1),
+            func: format,
+        },
+        Native {
+            name: "input",
+            arity: Arity::Fixed(1),
+            func: input,
+        },
+        Native {
+            name: "len",
+            arity: Arity::Fixed(1),
+            func: len,
+        },
+        Native {
+            name: "num",
+            arity: Arity::Fixed(1),
+            func: num,
+        },
+        Native {
+            name: "ord",
+            arity: Arity::Fixed(1),
+            func: ord,
+        },
+        Native {
+            name: "pop",
+            arity: Arity::Fixed(1),
+            func: pop,
+        },
+        Native {
+            name: "push",
+            arity: Arity::Fixed(2),
+            func: push,
+        },
+        Native {
+            name: "range",
+            arity: Arity::AtLeast(1),
+            func: range,
+        },
+        Native {
+            name: "str",
+            arity: Arity::Fixed(1),
+            func: str,
+        },
+        Native {
+            name: "type",
+            arity: Arity::Fixed(1),
+            func: type_of,
+        },
+    ];
+    #[cfg(feature = "std-io")]
+    natives.extend([
+        Native {
+            name: "read_file",
+            arity: Arity::Fixed(1),
+            func: read_file,
+        },
+        Native {
+            name: "write_file",
+            arity: Arity::Fixed(2),
+            func: write_file,
+        },
+    ]);
+    natives
+}
+
+/// Returns the one character string for a Unicode code point.
+fn chr(args: &[Value]) -> Result<Value, String> {
+    let code = match args[0] {
+        Value::Number(n) if n.fract() == 0.0 && n >= 0.0 && n <= u32::MAX as f64 => n as u32,
+        _ => return Err(S!("Argument to 'chr' must be a non-negative integer.")),
+    };
+    match char::from_u32(code) {
+        Some(c) => Ok(Value::Str(c.to_string())),
+        None => Err(format!("Invalid code point {}.", code)),
+    }
+}
+
+/// Replaces each `{}` in the format string with the next argument.
+fn format(args: &[Value]) -> Result<Value, String> {
+    let fmt = match &args[0] {
+        Value::Str(fmt) => fmt,
+        _ => return Err(S!("First argument to 'format' must be a string.")),
+    };
+    let pieces: Vec<&str> = fmt.split("{}").collect();
+    let values = &args[1..];
+    if pieces.len() - 1 != values.len() {
+        return Err(format!(
+            "Format string has {} placeholders but got {} arguments.",
+            pieces.len() - 1,
+            values.len()
+        ));
+    }
+    let mut formatted = S!(pieces[0]);
+    for (value, piece) in values.iter().zip(&pieces[1..]) {
+        formatted.push_str(&value.to_string());
+        formatted.push_str(piece);
+    }
+    Ok(Value::Str(formatted))
+}
+
+/// Prints the prompt and reads a line from stdin, nil at the end of input.
+fn input(args: &[Value]) -> Result<Value, String> {
+    read_line(&mut stdin().lock(), &mut stdout(), &args[0]).map_err(|err| err.to_string())
+}
+
+/// Writes `prompt` without a newline, then reads a line without its line
+/// ending, or nil when the reader is exhausted.
+fn read_line(
+    reader: &mut impl BufRead,
+    writer: &mut impl Write,
+    prompt: &Value,
+) -> io::Result<Value> {
+    write!(writer, "{}", prompt)?;
+    writer.flush()?;
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(Value::Nil);
+    }
+    let len = line.trim_end_matches(['\n', '\r']).len();
+    line.truncate(len);
+    Ok(Value::Str(line))
+}
+
+/// Returns the number of characters in a string or elements in a list.
+fn len(args: &[Value]) -> Result<Value, String> {
+    match &args[0] {
+        Value::Str(s) => Ok(Value::Number(s.chars().count() as f64)),
+        Value::List(list) => Ok(Value::Number(list.borrow().len() as f64)),
+        _ => Err(S!("Argument to 'len' must be a string or list.")),
+    }
+}
+
+/// Parses a string into a number, returning nil when it isn't one.
+fn num(args: &[Value]) -> Result<Value, String> {
+    match &args[0] {
+        Value::Number(n) => Ok(Value::Number(*n)),
+        Value::Str(s) => Ok(match s.trim().parse::<f64>() {
+            Ok(n) if n.is_finite() => Value::Number(n),
+            _ => Value::Nil,
+        }),
+        _ => Err(S!("Argument to 'num' must be a string or number.")),
+    }
+}
+
+/// Returns the Unicode code point of a one character string.
+fn ord(args: &[Value]) -> Result<Value, String> {
+    if let Value::Str(s) = &args[0] {
+        let mut chars = s.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            return Ok(Value::Number(c as u32 as f64));
+        }
+    }
+    Err(S!("Argument to 'ord' must be a one character string."))
+}
+
+/// Removes and returns the last element of a list, nil when it's empty.
+fn pop(args: &[Value]) -> Result<Value, String> {
+    match &args[0] {
+        Value::List(list) => Ok(list.borrow_mut().pop().unwrap_or(Value::Nil)),
+        _ => Err(S!("Argument to 'pop' must be a list.")),
+    }
+}
+
+/// Appends a value to a list, returning its new length.
+fn push(args: &[Value]) -> Result<Value, String> {
+    match &args[0] {
+        Value::List(list) => {
+            let mut list = list.borrow_mut();
+            list.push(args[1].clone());
+            Ok(Value::Number(list.len() as f64))
+        }
+        _ => Err(S!("First argument to 'push' must be a list.")),
+    }
+}
+
+/// The most elements `range` builds, so a typo'd bound is an error rather
+/// than an allocation that takes down the process.
+const MAX_RANGE: i64 = 1 << 24;
+
+/// Returns the list of integers from a start, zero by default, up to but
+/// not including an end.
+fn range(args: &[Value]) -> Result<Value, String> {
+    let bounds: Vec<i64> = args
+        .iter()
+        .map(|arg| match arg {
+            Value::Number(n) if n.fract() == 0.0 && n.abs() <= MAX_EXACT_INTEGER => Ok(*n as i64),
+            _ => Err(S!("Arguments to 'range' must be integers.")),
+        })
+        .collect::<Result<_, _>>()?;
+    let (start, end) = match bounds[..] {
+        [end] => (0, end),
+        [start, end] => (start, end),
+        _ => return Err(format!("Expected 1 or 2 arguments but got {}.", args.len())),
+    };
+    // Bounds are within 2^53 so the difference can't overflow
+    if end - start > MAX_RANGE {
+        return Err(S!("Range is too large."));
+    }
+    let list = (start..end).map(|n| Value::Number(n as f64)).collect();
+    Ok(Value::List(Rc::new(RefCell::new(list))))
+}
+
+/// Formats any value the way it is printed.
+fn str(args: &[Value]) -> Result<Value, String> {
+    Ok(Value::Str(args[0].to_string()))
+}
+
+/// Returns the contents of the file at a path, nil when it can't be read.
+#[cfg(feature = "std-io")]
+fn read_file(args: &[Value]) -> Result<Value, String> {
+    match &args[0] {
+        Value::Str(path) => Ok(std::fs::read_to_string(path).map_or(Value::Nil, Value::Str)),
+        _ => Err(S!("Argument to 'read_file' must be a string.")),
+    }
+}
+
+/// Writes a value, formatted as it is printed, to the file at a path.
+/// Returns whether the write succeeded.
+#[cfg(feature = "std-io")]
+fn write_file(args: &[Value]) -> Result<Value, String> {
+    match &args[0] {
+        Value::Str(path) => Ok(Value::Bool(
+            std::fs::write(path, args[1].to_string()).is_ok(),
+        )),
+        _ => Err(S!("First argument to 'write_file' must be a string.")),
+    }
+}
+
+/// Returns the name of the value's type.
+fn type_of(args: &[Value]) -> Result<Value, String> {
+    let name = match &args[0] {
+        Value::Nil => "nil",
+        Value::Bool(_) => "bool",
+        Value::Number(_) => "number",
+        Value::Str(_) => "string",
+        Value::List(_) => "list",
+        Value::Map(_) => "map",
+        Value::Native(_) => "function",
+    };
+    Ok(Value::Str(S!(name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_line() {
+        let reader = &mut "first\r\nsecond".as_bytes();
+        let mut out = Vec::new();
+        let prompt = Value::Str(S!("name? "));
+        assert_eq!(
+            Value::Str(S!("first")),
+            read_line(reader, &mut out, &prompt).unwrap()
+        );
+        assert_eq!(
+            Value::Str(S!("second")),
+            read_line(reader, &mut out, &Value::Number(2.0)).unwrap()
+        );
+        assert_eq!(
+            Value::Nil,
+            read_line(reader, &mut out, &Value::Nil).unwrap()
+        );
+        assert_eq!("name? 2nil", String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn test_push_pop() {
+        let list = Value::List(Default::default());
+        let push_arg = |value| push(&[list.clone(), value]);
+        assert_eq!(Ok(Value::Number(1.0)), push_arg(Value::Number(1.0)));
+        assert_eq!(Ok(Value::Number(2.0)), push_arg(Value::Str(S!("two"))));
+        // The list is shared, so a push through one handle is seen by all
+        assert_eq!("[1, two]", list.to_string());
+
+        let pop_list = || pop(std::slice::from_ref(&list));
+        assert_eq!(Ok(Value::Str(S!("two"))), pop_list());
+        assert_eq!(Ok(Value::Number(1.0)), pop_list());
+        assert_eq!(Ok(Value::Nil), pop_list());
+
+        assert_eq!(
+            Err(S!("First argument to 'push' must be a list.")),
+            push(&[Value::Nil, Value::Nil])
+        );
+        assert_eq!(
+            Err(S!("Argument to 'pop' must be a list.")),
+            pop(&[Value::Str(S!("abc"))])
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "std-io")]
+    fn test_file_round_trip() {
+        let dir = std::env::temp_dir().join(format!("rlox_natives_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = Value::Str(dir.join("note.txt").to_string_lossy().into_owned());
+
+        let contents = Value::Str(S!("line one\nline two"));
+        assert_eq!(
+            Ok(Value::Bool(true)),
+            write_file(&[path.clone(), contents.clone()])
+        );
+        assert_eq!(Ok(contents), read_file(std::slice::from_ref(&path)));
+        assert_eq!(
+            Ok(Value::Bool(true)),
+            write_file(&[path.clone(), Value::Number(2.5)])
+        );
+        assert_eq!(Ok(Value::Str(S!("2.5"))), read_file(&[path]));
+
+        let missing = Value::Str(dir.join("missing.txt").to_string_lossy().into_owned());
+        assert_eq!(Ok(Value::Nil), read_file(&[missing]));
+        let unwritable = Value::Str(dir.join("no/such/dir").to_string_lossy().into_owned());
+        assert_eq!(
+            Ok(Value::Bool(false)),
+            write_file(&[unwritable, Value::Nil])
+        );
+        assert!(read_file(&[Value::Nil]).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
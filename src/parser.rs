@@ -1,81 +1,418 @@
-use crate::expression::{Bin, Cond, Expr, Grp, Lit, Un};
+use crate::error_fmt::{report_errors, Error};
+use crate::expression::{
+    ArrayLit, Bin, Call, Cond, Expr, ExprId, Grp, Index, Lit, Logical, MapLit, Node, Seq,
+    SourceMap, Span, Un, Var,
+};
 use crate::marcher::Marcher;
-use crate::token::{Token, TokenType};
+use crate::token::{Literal, Token, TokenType};
+use crate::S;
+
+pub(crate) mod precedence;
 
 /*                    Grammer for lox
  * --------------------------------------------------------
- * expression -> ternary;
- * ternary    -> equality ? expression : expression;
+ * expression -> ternary ( "," ternary )*;
+ * ternary    -> coalesce ? expression : expression;
+ * coalesce   -> or ( "??" or )*;
+ * or         -> and ( "or" and )*;
+ * and        -> equality ( "and" equality )*;
  * equality   -> comparison ( ("=" | "!=") comparison )*;
- * comparison -> term ( (">" | ">=" | "<" | "<=") term )*;
- * term       -> factor ( ("*" | "/") factor)*;
- * factor     -> unary ( ("+" | "-") unary)*;
- * unary      -> ("!" | "-") unary | primary
- * primary    -> NUMBER | STRING | "true" | "false" | "nil" | "(" expression ")";
+ * comparison -> bit_or ( (">" | ">=" | "<" | "<=") bit_or )*;
+ * bit_or     -> bit_xor ( "|" bit_xor )*;
+ * bit_xor    -> bit_and ( "^" bit_and )*;
+ * bit_and    -> shift ( "&" shift )*;
+ * shift      -> term ( ("<<" | ">>") term )*;
+ * term       -> factor ( ("+" | "-") factor)*;
+ * factor     -> unary ( ("*" | "/" | "div" | "%") unary)*;
+ * unary      -> ("!" | "-") unary | call
+ * call       -> primary ( "(" arguments? ")" | "[" expression "]" )*;
+ * arguments  -> ternary ( "," ternary )*;
+ * primary    -> NUMBER | STRING | "true" | "false" | "nil" | IDENTIFIER | "(" expression ")"
+ *             | "[" arguments? "]" | "{" entries? "}";
+ * entries    -> ternary ":" ternary ( "," ternary ":" ternary )*;
  */
 
 pub struct Parser {
+    /// Parse binary operators with `precedence::binary` rather than one
+    /// method per level.
+    climbing: bool,
+    errors: Vec<Error>,
+    /// Lines of the source text, used to show the offending line in errors.
+    lines: Vec<String>,
+    /// Report `a < b < c` instead of comparing a bool with `c`.
+    reject_chained: bool,
+    source_map: SourceMap,
+    source_name: String,
     tokens: Marcher<Token>,
 }
 
-pub fn parse(tokens: &Vec<Token>) -> Box<dyn Expr> {
-    let mut parser = Parser::new(tokens);
-    parser.expression()
+/// Parses the tokens into an expression, reporting any syntax errors found.
+pub fn parse(tokens: &[Token], source_name: &str) -> Box<dyn Expr> {
+    let mut parser = Parser::new(tokens, source_name);
+    let expr = parser.parse();
+    if !parser.errors.is_empty() {
+        report_errors(&parser.errors);
+    }
+    expr
+}
+
+/// Parses the tokens into an expression, returning the syntax errors instead
+/// of printing them.
+pub fn try_parse(
+    tokens: &[Token],
+    source: &str,
+    source_name: &str,
+) -> Result<Box<dyn Expr>, Vec<Error>> {
+    parse_with_source_map(tokens, source, source_name).map(|(expr, _)| expr)
+}
+
+/// Like `try_parse`, also returning the span of every node in the tree.
+pub fn parse_with_source_map(
+    tokens: &[Token],
+    source: &str,
+    source_name: &str,
+) -> Result<(Box<dyn Expr>, SourceMap), Vec<Error>> {
+    let mut parser = Parser::new(tokens, source_name).with_source(source);
+    let expr = parser.parse();
+    match parser.errors.is_empty() {
+        true => Ok((expr, parser.source_map)),
+        false => Err(parser.errors),
+    }
+}
+
+/// Like `try_parse`, but parses binary operators by precedence climbing.
+pub fn parse_precedence(
+    tokens: &[Token],
+    source: &str,
+    source_name: &str,
+) -> Result<Box<dyn Expr>, Vec<Error>> {
+    let mut parser = Parser::new(tokens, source_name).with_source(source);
+    parser.climbing = true;
+    let expr = parser.parse();
+    match parser.errors.is_empty() {
+        true => Ok(expr),
+        false => Err(parser.errors),
+    }
 }
 
 impl Parser {
-    pub fn new(tokens: &Vec<Token>) -> Self {
+    pub fn new(tokens: &[Token], source_name: &str) -> Self {
         Parser {
+            climbing: false,
+            errors: Vec::new(),
+            lines: Vec::new(),
+            reject_chained: false,
+            source_map: SourceMap::default(),
+            source_name: S!(source_name),
             tokens: Marcher::new(tokens.to_vec()),
         }
     }
 
+    /// Sets the source text the tokens were scanned from. Without it errors
+    /// only show the offending lexeme.
+    pub fn with_source(mut self, source: &str) -> Self {
+        self.lines = source.lines().map(String::from).collect();
+        self
+    }
+
+    /// Makes a comparison whose left operand is itself a comparison an error.
+    /// Off by default since `(a < b) < c` is valid, if rarely intended.
+    pub fn reject_chained_comparisons(mut self) -> Self {
+        self.reject_chained = true;
+        self
+    }
+
+    /// Parses a single expression that must consume every token.
+    fn parse(&mut self) -> Box<dyn Expr> {
+        let expr = self.expression();
+        if self.errors.is_empty() && !self.at_end() {
+            self.error_at_next("Expect end of expression.");
+        }
+        expr
+    }
+
+    /// Whether only the `Eof` token, if any, is left.
+    fn at_end(&self) -> bool {
+        self.tokens
+            .peek(1)
+            .is_none_or(|t| t.token_type == TokenType::Eof)
+    }
+
+    /// Advances past the next token if it has the expected type, otherwise
+    /// returns an error located at it.
+    fn consume(&mut self, token_type: TokenType, message: &str) -> Result<&Token, Error> {
+        if self
+            .tokens
+            .peek(1)
+            .is_some_and(|t| t.token_type == token_type)
+        {
+            return Ok(self.tokens.advance(1).unwrap());
+        }
+        Err(self.next_error(message))
+    }
+
+    /// Whether the next two tokens have the given types, consuming neither.
+    fn check_two(&self, a: TokenType, b: TokenType) -> bool {
+        self.tokens.peek(1).is_some_and(|t| t.token_type == a)
+            && self.tokens.peek(2).is_some_and(|t| t.token_type == b)
+    }
+
+    /// Records the error when the next token isn't of the expected type.
+    fn expect(&mut self, token_type: TokenType, message: &str) -> bool {
+        match self.consume(token_type, message) {
+            Ok(_) => true,
+            Err(error) => {
+                self.errors.push(error);
+                false
+            }
+        }
+    }
+
+    /// Reports an error at the next unconsumed token, or at the last token
+    /// when the input ran out.
+    fn error_at_next(&mut self, message: &str) {
+        let error = self.next_error(message);
+        self.errors.push(error)
+    }
+
+    fn next_error(&self, message: &str) -> Error {
+        let next = match self.at_end() {
+            true => self.tokens.peek(0).or_else(|| self.tokens.peek(1)),
+            false => self.tokens.peek(1),
+        };
+        match next {
+            Some(token) => self.error(token, message),
+            None => Error::new(
+                S!("Syntax Error: ") + message,
+                String::new(),
+                self.source_name.clone(),
+                1,
+                0,
+            ),
+        }
+    }
+
+    /// Discards tokens until a likely statement boundary so one mistake
+    /// doesn't produce a cascade of errors.
+    fn synchronize(&mut self) {
+        while let Some(next) = self.tokens.peek(1) {
+            if matches!(
+                next.token_type,
+                TokenType::Class
+                    | TokenType::Fun
+                    | TokenType::Var
+                    | TokenType::For
+                    | TokenType::If
+                    | TokenType::While
+                    | TokenType::Print
+                    | TokenType::Return
+                    | TokenType::Eof
+            ) {
+                return;
+            }
+            if self.tokens.advance(1).unwrap().token_type == TokenType::Semicolon {
+                return;
+            }
+        }
+    }
+
+    fn add_error(&mut self, token: &Token, message: String) {
+        let error = self.error(token, &message);
+        self.errors.push(error)
+    }
+
+    fn error(&self, token: &Token, message: &str) -> Error {
+        Error::new(
+            S!("Syntax Error: ") + message,
+            self.lines
+                .get(token.line.wrapping_sub(1))
+                .unwrap_or(&token.lexeme)
+                .clone(),
+            self.source_name.clone(),
+            token.line,
+            token.col,
+        )
+        .with_width(token.span.len())
+    }
+
+    /// Reports `op` when chained comparisons are rejected and it compares the
+    /// result of another comparison.
+    fn check_chained(&mut self, left: &dyn Expr, op: &Token) {
+        if self.reject_chained && is_comparison(op) {
+            if let Node::Bin(bin) = left.node() {
+                if is_comparison(&bin.operator) {
+                    self.add_error(
+                        op,
+                        S!("Chained comparisons are not supported; use explicit parentheses."),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Reports a binary operator found where an operand should start, then
+    /// parses and discards its right operand to recover.
+    fn missing_left_operand(
+        &mut self,
+        operators: &[TokenType],
+        operand: fn(&mut Self) -> Box<dyn Expr>,
+    ) -> Option<Box<dyn Expr>> {
+        let op = self
+            .tokens
+            .advance_if(|t| operators.contains(&t.token_type))?
+            .clone();
+        self.add_error(
+            &op,
+            format!("Binary operator '{}' has no left operand.", op.lexeme),
+        );
+        operand(self);
+        Some(self.placeholder(start_of(&op)))
+    }
+
+    /// Records the span of a node from `start` to the last consumed token.
+    /// Ids are taken once a node's tokens are consumed, so struct literals
+    /// list `id` after the fields that parse children.
+    fn node_id(&mut self, start: (usize, usize)) -> ExprId {
+        let end = self.tokens.peek(0).map_or(start, |t| (t.line, t.span.end));
+        self.source_map.add(Span { start, end })
+    }
+
+    /// Like `node_id` for a node starting where `first` does.
+    fn node_id_from(&mut self, first: &dyn Expr) -> ExprId {
+        let start = self
+            .source_map
+            .span(first.id())
+            .map_or((1, 0), |span| span.start);
+        self.node_id(start)
+    }
+
+    /// The end of the last consumed token.
+    fn position(&self) -> (usize, usize) {
+        self.tokens.peek(0).map_or((1, 0), |t| (t.line, t.span.end))
+    }
+
+    /// A `nil` standing in for an expression that failed to parse.
+    fn placeholder(&mut self, start: (usize, usize)) -> Box<dyn Expr> {
+        Box::new(Lit {
+            value: Literal::Nil,
+            id: self.node_id(start),
+        })
+    }
+
     fn expression(&mut self) -> Box<dyn Expr> {
-        let mut expr = self.ternary();
+        let expr = self.ternary();
+        let mut exprs = vec![expr];
         while self
             .tokens
             .advance_if(|t| t.token_type == TokenType::Comma)
             .is_some()
         {
-            expr = self.equality();
+            exprs.push(self.ternary());
         }
-        expr
+
+        if exprs.len() == 1 {
+            return exprs.pop().unwrap();
+        }
+        Box::new(Seq {
+            id: self.node_id_from(exprs[0].as_ref()),
+            exprs,
+        })
     }
 
     fn ternary(&mut self) -> Box<dyn Expr> {
-        let mut expr: Box<dyn Expr> = self.equality();
+        let mut expr: Box<dyn Expr> = match self.climbing {
+            true => self.binary(1),
+            false => self.coalesce(),
+        };
         if self
             .tokens
             .advance_if(|t| t.token_type == TokenType::Question)
             .is_some()
         {
+            let cons = self.expression();
+            let alt: Box<dyn Expr> =
+                match self.expect(TokenType::Colon, "Expect ':' in ternary expression.") {
+                    // Recursing into ternary keeps chains right associative
+                    // without letting the else branch swallow a comma
+                    true => self.ternary(),
+                    false => {
+                        self.synchronize();
+                        self.placeholder(self.position())
+                    }
+                };
             expr = Box::new(Cond {
+                id: self.node_id_from(expr.as_ref()),
                 cond: expr,
-                cons: self.expression(),
-                alt: {
-                    if self
-                        .tokens
-                        .advance_if(|t| t.token_type == TokenType::Colon)
-                        .is_none()
-                    {
-                        panic!("No alternate condition provided")
-                    }
-                    self.expression()
-                },
+                cons,
+                alt,
+            })
+        }
+        expr
+    }
+
+    fn coalesce(&mut self) -> Box<dyn Expr> {
+        let mut expr: Box<dyn Expr> = self
+            .missing_left_operand(&[TokenType::QuestionQuestion], Self::or)
+            .unwrap_or_else(|| self.or());
+        while let Some(op) = self
+            .tokens
+            .advance_if(|t| t.token_type == TokenType::QuestionQuestion)
+        {
+            expr = Box::new(Logical {
+                operator: op.clone(),
+                right: self.or(),
+                id: self.node_id_from(expr.as_ref()),
+                left: expr,
+            })
+        }
+        expr
+    }
+
+    fn or(&mut self) -> Box<dyn Expr> {
+        let mut expr: Box<dyn Expr> = self
+            .missing_left_operand(&[TokenType::Or], Self::and)
+            .unwrap_or_else(|| self.and());
+        while let Some(op) = self.tokens.advance_if(|t| t.token_type == TokenType::Or) {
+            expr = Box::new(Logical {
+                operator: op.clone(),
+                right: self.and(),
+                id: self.node_id_from(expr.as_ref()),
+                left: expr,
+            })
+        }
+        expr
+    }
+
+    fn and(&mut self) -> Box<dyn Expr> {
+        let mut expr: Box<dyn Expr> = self
+            .missing_left_operand(&[TokenType::And], Self::equality)
+            .unwrap_or_else(|| self.equality());
+        while let Some(op) = self.tokens.advance_if(|t| t.token_type == TokenType::And) {
+            expr = Box::new(Logical {
+                operator: op.clone(),
+                right: self.equality(),
+                id: self.node_id_from(expr.as_ref()),
+                left: expr,
             })
         }
         expr
     }
 
     fn equality(&mut self) -> Box<dyn Expr> {
-        let mut expr: Box<dyn Expr> = self.comparison();
+        let mut expr: Box<dyn Expr> = self
+            .missing_left_operand(
+                &[TokenType::BangEqual, TokenType::EqualEqual],
+                Self::comparison,
+            )
+            .unwrap_or_else(|| self.comparison());
         while let Some(op) = self.tokens.advance_if(|t| {
             t.token_type == TokenType::BangEqual || t.token_type == TokenType::EqualEqual
         }) {
             expr = Box::new(Bin {
-                left: expr,
                 operator: op.clone(),
                 right: self.comparison(),
+                id: self.node_id_from(expr.as_ref()),
+                left: expr,
             })
         }
 
@@ -83,7 +420,17 @@ impl Parser {
     }
 
     fn comparison(&mut self) -> Box<dyn Expr> {
-        let mut expr: Box<dyn Expr> = self.term();
+        let mut expr: Box<dyn Expr> = self
+            .missing_left_operand(
+                &[
+                    TokenType::Greater,
+                    TokenType::GreaterEqual,
+                    TokenType::LessEqual,
+                    TokenType::Less,
+                ],
+                Self::bit_or,
+            )
+            .unwrap_or_else(|| self.bit_or());
 
         while let Some(op) = self.tokens.advance_if(|t| {
             t.token_type == TokenType::Greater
@@ -91,40 +438,128 @@ impl Parser {
                 || t.token_type == TokenType::LessEqual
                 || t.token_type == TokenType::Less
         }) {
+            let op = op.clone();
+            self.check_chained(expr.as_ref(), &op);
             expr = Box::new(Bin {
+                operator: op,
+                right: self.bit_or(),
+                id: self.node_id_from(expr.as_ref()),
                 left: expr,
+            })
+        }
+        expr
+    }
+
+    fn bit_or(&mut self) -> Box<dyn Expr> {
+        let mut expr: Box<dyn Expr> = self
+            .missing_left_operand(&[TokenType::Pipe], Self::bit_xor)
+            .unwrap_or_else(|| self.bit_xor());
+        while let Some(op) = self.tokens.advance_if(|t| t.token_type == TokenType::Pipe) {
+            expr = Box::new(Bin {
                 operator: op.clone(),
-                right: self.term(),
+                right: self.bit_xor(),
+                id: self.node_id_from(expr.as_ref()),
+                left: expr,
             })
         }
         expr
     }
 
-    fn term(&mut self) -> Box<dyn Expr> {
-        let mut expr: Box<dyn Expr> = self.factor();
+    fn bit_xor(&mut self) -> Box<dyn Expr> {
+        let mut expr: Box<dyn Expr> = self
+            .missing_left_operand(&[TokenType::Caret], Self::bit_and)
+            .unwrap_or_else(|| self.bit_and());
+        while let Some(op) = self.tokens.advance_if(|t| t.token_type == TokenType::Caret) {
+            expr = Box::new(Bin {
+                operator: op.clone(),
+                right: self.bit_and(),
+                id: self.node_id_from(expr.as_ref()),
+                left: expr,
+            })
+        }
+        expr
+    }
+
+    fn bit_and(&mut self) -> Box<dyn Expr> {
+        let mut expr: Box<dyn Expr> = self
+            .missing_left_operand(&[TokenType::Ampersand], Self::shift)
+            .unwrap_or_else(|| self.shift());
         while let Some(op) = self
             .tokens
-            .advance_if(|t| t.token_type == TokenType::Plus || t.token_type == TokenType::Minus)
+            .advance_if(|t| t.token_type == TokenType::Ampersand)
         {
             expr = Box::new(Bin {
+                operator: op.clone(),
+                right: self.shift(),
+                id: self.node_id_from(expr.as_ref()),
                 left: expr,
+            })
+        }
+        expr
+    }
+
+    fn shift(&mut self) -> Box<dyn Expr> {
+        let mut expr: Box<dyn Expr> = self
+            .missing_left_operand(
+                &[TokenType::LessLess, TokenType::GreaterGreater],
+                Self::term,
+            )
+            .unwrap_or_else(|| self.term());
+        while let Some(op) = self.tokens.advance_if(|t| {
+            t.token_type == TokenType::LessLess || t.token_type == TokenType::GreaterGreater
+        }) {
+            expr = Box::new(Bin {
                 operator: op.clone(),
-                right: self.factor(),
+                right: self.term(),
+                id: self.node_id_from(expr.as_ref()),
+                left: expr,
             })
         }
         expr
     }
 
-    fn factor(&mut self) -> Box<dyn Expr> {
-        let mut expr: Box<dyn Expr> = self.unary();
+    fn term(&mut self) -> Box<dyn Expr> {
+        // A leading minus is a unary negation, not a missing operand
+        let mut expr: Box<dyn Expr> = self
+            .missing_left_operand(&[TokenType::Plus], Self::factor)
+            .unwrap_or_else(|| self.factor());
         while let Some(op) = self
             .tokens
-            .advance_if(|t| t.token_type == TokenType::Slash || t.token_type == TokenType::Star)
+            .advance_if(|t| t.token_type == TokenType::Plus || t.token_type == TokenType::Minus)
         {
             expr = Box::new(Bin {
+                operator: op.clone(),
+                right: self.factor(),
+                id: self.node_id_from(expr.as_ref()),
                 left: expr,
+            })
+        }
+        expr
+    }
+
+    fn factor(&mut self) -> Box<dyn Expr> {
+        let mut expr: Box<dyn Expr> = self
+            .missing_left_operand(
+                &[
+                    TokenType::Slash,
+                    TokenType::Star,
+                    TokenType::Div,
+                    TokenType::Percent,
+                ],
+                Self::unary,
+            )
+            .unwrap_or_else(|| self.unary());
+        while let Some(op) = self.tokens.advance_if(|t| {
+            t.token_type == TokenType::Slash
+                || t.token_type == TokenType::Star
+                || t.token_type == TokenType::Div
+                || t.token_type == TokenType::Percent
+        }) {
+            expr = Box::new(Bin {
                 operator: op.clone(),
                 right: self.unary(),
+                id: self.node_id_from(expr.as_ref()),
+                left: expr,
             });
         }
         expr
@@ -135,63 +570,649 @@ impl Parser {
             .tokens
             .advance_if(|t| t.token_type == TokenType::Bang || t.token_type == TokenType::Minus)
         {
-            let expr = Box::new(Un {
-                operator: op.clone(),
-                right: self.unary(),
+            let operator = op.clone();
+            let right = self.unary();
+            return Box::new(Un {
+                id: self.node_id(start_of(&operator)),
+                operator,
+                right,
             });
-            return expr;
         };
 
-        self.primary()
+        self.call()
+    }
+
+    fn call(&mut self) -> Box<dyn Expr> {
+        let mut expr: Box<dyn Expr> = self.primary();
+        while let Some(t) = self.tokens.advance_if(|t| {
+            t.token_type == TokenType::LeftParen || t.token_type == TokenType::LeftBracket
+        }) {
+            let open = t.clone();
+            if open.token_type == TokenType::LeftBracket {
+                let bracket = open;
+                let index = self.expression();
+                self.expect(TokenType::RightBracket, "Expect ']' after index.");
+                expr = Box::new(Index {
+                    id: self.node_id_from(expr.as_ref()),
+                    object: expr,
+                    bracket,
+                    index,
+                });
+                continue;
+            }
+
+            let arguments = self.arguments(TokenType::RightParen);
+            let paren = match self.consume(TokenType::RightParen, "Expect ')' after arguments.") {
+                Ok(paren) => paren.clone(),
+                Err(error) => {
+                    self.errors.push(error);
+                    open
+                }
+            };
+            expr = Box::new(Call {
+                id: self.node_id_from(expr.as_ref()),
+                callee: expr,
+                paren,
+                arguments,
+            });
+        }
+
+        // Nothing can be assigned to yet, so every increment target is invalid
+        if let Some(op) = self.tokens.advance_if(|t| {
+            t.token_type == TokenType::PlusPlus || t.token_type == TokenType::MinusMinus
+        }) {
+            let op = op.clone();
+            self.add_error(&op, format!("Invalid '{}' target.", op.lexeme));
+        }
+        expr
+    }
+
+    /// Parses a possibly empty comma separated list up to, but not including, `close`.
+    fn arguments(&mut self, close: TokenType) -> Vec<Box<dyn Expr>> {
+        let mut arguments = Vec::new();
+        if !self.at_end() && self.tokens.peek(1).is_some_and(|t| t.token_type != close) {
+            // Arguments are ternaries so the comma operator doesn't swallow them
+            arguments.push(self.ternary());
+            while self
+                .tokens
+                .advance_if(|t| t.token_type == TokenType::Comma)
+                .is_some()
+            {
+                arguments.push(self.ternary());
+            }
+        }
+        arguments
+    }
+
+    fn entries(&mut self) -> Vec<(Box<dyn Expr>, Box<dyn Expr>)> {
+        let mut entries = Vec::new();
+        if self
+            .tokens
+            .advance_if(|t| t.token_type == TokenType::RightBrace)
+            .is_some()
+        {
+            return entries;
+        }
+
+        loop {
+            let key = self.ternary();
+            self.expect(TokenType::Colon, "Expect ':' after map key.");
+            entries.push((key, self.ternary()));
+            if self
+                .tokens
+                .advance_if(|t| t.token_type == TokenType::Comma)
+                .is_none()
+            {
+                break;
+            }
+        }
+
+        self.expect(TokenType::RightBrace, "Expect '}' after map entries.");
+        entries
     }
 
     fn primary(&mut self) -> Box<dyn Expr> {
-        let mut expr: Box<dyn Expr> = Box::new(Lit { value: None });
-        if let Some(t) = self.tokens.advance_if(|t| {
+        // A brace before a declaration would open a block, which has no value
+        if self.check_two(TokenType::LeftBrace, TokenType::Var) {
+            self.error_at_next("Expect expression, a block is not a value.");
+            let start = self.tokens.advance(1).map_or((1, 0), start_of);
+            self.synchronize();
+            return self.placeholder(start);
+        }
+
+        let Some(t) = self.tokens.advance_if(|t| {
             t.token_type == TokenType::True
+                || t.token_type == TokenType::False
                 || t.token_type == TokenType::Nil
                 || t.token_type == TokenType::String
                 || t.token_type == TokenType::Number
+                || t.token_type == TokenType::Identifier
                 || t.token_type == TokenType::LeftParen
-        }) {
-            match &t.token_type {
-                TokenType::True => {
-                    expr = Box::new(Lit {
-                        value: Some(Box::new(true)),
-                    });
-                }
-                TokenType::Nil => {
-                    expr = Box::new(Lit { value: None });
-                }
-                TokenType::String => {
-                    expr = Box::new(Lit {
-                        value: Some(Box::new(t.literal.clone().unwrap().as_string())),
-                    });
-                }
-                TokenType::Number => {
-                    expr = Box::new(Lit {
-                        value: Some(Box::new(t.literal.clone().unwrap().as_number())),
-                    });
-                }
-                TokenType::LeftParen => {
-                    expr = Box::new(Grp {
-                        expression: self.expression(),
-                    });
-                    // Ensure there is a closing paren and consume it
-                    if self
-                        .tokens
-                        .advance_if(|t| t.token_type == TokenType::RightParen)
-                        .is_none()
-                    {
-                        panic!("Invalid token to start an expression.")
-                    };
+                || t.token_type == TokenType::LeftBracket
+                || t.token_type == TokenType::LeftBrace
+        }) else {
+            self.error_at_next("Invalid token to start an expression.");
+            return self.placeholder(self.position());
+        };
+
+        let t = t.clone();
+        let start = start_of(&t);
+        match t.token_type {
+            TokenType::True => Box::new(Lit {
+                value: Literal::Bool(true),
+                id: self.node_id(start),
+            }),
+            TokenType::False => Box::new(Lit {
+                value: Literal::Bool(false),
+                id: self.node_id(start),
+            }),
+            TokenType::Nil => Box::new(Lit {
+                value: Literal::Nil,
+                id: self.node_id(start),
+            }),
+            TokenType::String | TokenType::Number => Box::new(Lit {
+                value: t.literal.clone().unwrap_or(Literal::Nil),
+                id: self.node_id(start),
+            }),
+            TokenType::Identifier => Box::new(Var {
+                id: self.node_id(start),
+                name: t,
+            }),
+            TokenType::LeftBracket => {
+                let elements = self.arguments(TokenType::RightBracket);
+                self.expect(TokenType::RightBracket, "Expect ']' after list elements.");
+                Box::new(ArrayLit {
+                    elements,
+                    id: self.node_id(start),
+                })
+            }
+            TokenType::LeftBrace => {
+                // Without statements a brace in expression position is always a map
+                let entries = self.entries();
+                Box::new(MapLit {
+                    entries,
+                    id: self.node_id(start),
+                    brace: t,
+                })
+            }
+            TokenType::LeftParen => {
+                let expression = self.expression();
+                let closed = self.expect(TokenType::RightParen, "Expect ')' after expression.");
+                let id = self.node_id(start);
+                if !closed {
+                    self.synchronize();
                 }
-                _ => {}
+                Box::new(Grp {
+                    id,
+                    paren: t,
+                    expression,
+                })
             }
-        } else {
-            panic!("Invalid token to start an expression.")
+            _ => self.placeholder(start),
+        }
+    }
+}
+
+fn start_of(token: &Token) -> (usize, usize) {
+    (token.line, token.span.start)
+}
+
+fn is_comparison(token: &Token) -> bool {
+    matches!(
+        token.token_type,
+        TokenType::Greater | TokenType::GreaterEqual | TokenType::Less | TokenType::LessEqual
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expression::Visitor;
+    use crate::scanner::scan_tokens;
+    use crate::S;
+
+    struct LitCollector(Vec<Literal>);
+
+    impl Visitor<()> for LitCollector {
+        fn visit_array_lit(&mut self, expr: &ArrayLit) {
+            for element in &expr.elements {
+                element.accept(self);
+            }
+        }
+
+        fn visit_bin(&mut self, expr: &Bin) {
+            expr.left.accept(self);
+            expr.right.accept(self);
+        }
+
+        fn visit_call(&mut self, expr: &Call) {
+            expr.callee.accept(self);
+            for arg in &expr.arguments {
+                arg.accept(self);
+            }
+        }
+
+        fn visit_cond(&mut self, expr: &Cond) {
+            expr.cond.accept(self);
+            expr.cons.accept(self);
+            expr.alt.accept(self);
+        }
+
+        fn visit_grp(&mut self, expr: &Grp) {
+            expr.expression.accept(self);
+        }
+
+        fn visit_index(&mut self, expr: &Index) {
+            expr.object.accept(self);
+            expr.index.accept(self);
+        }
+
+        fn visit_lit(&mut self, expr: &Lit) {
+            self.0.push(expr.value.clone());
+        }
+
+        fn visit_logical(&mut self, expr: &Logical) {
+            expr.left.accept(self);
+            expr.right.accept(self);
+        }
+
+        fn visit_map_lit(&mut self, expr: &MapLit) {
+            for (key, value) in &expr.entries {
+                key.accept(self);
+                value.accept(self);
+            }
+        }
+
+        fn visit_seq(&mut self, expr: &Seq) {
+            for expr in &expr.exprs {
+                expr.accept(self);
+            }
+        }
+
+        fn visit_un(&mut self, expr: &Un) {
+            expr.right.accept(self);
+        }
+
+        fn visit_var(&mut self, _expr: &Var) {}
+    }
+
+    #[test]
+    fn test_parse_literals() {
+        let expr = parse(
+            &scan_tokens(&S!("true == (nil ? \"a\" : 1)"), "<test>"),
+            "<test>",
+        );
+        let mut collector = LitCollector(Vec::new());
+        expr.accept(&mut collector);
+        assert_eq!(
+            vec![
+                Literal::Bool(true),
+                Literal::Nil,
+                Literal::String(S!("a")),
+                Literal::Number(1.0)
+            ],
+            collector.0
+        );
+        assert_eq!(Some(true), collector.0[0].as_bool());
+        assert!(collector.0[1].is_nil());
+        assert_eq!("(== true (grp (nil ? \"a\" : 1)))", expr.to_string());
+    }
+
+    #[test]
+    fn test_parse_bool_literals() {
+        for (source, value) in [("true", true), ("false", false)] {
+            let expr = parse(&scan_tokens(&S!(source), "<test>"), "<test>");
+            match expr.node() {
+                Node::Lit(lit) => assert_eq!(Some(value), lit.value.as_bool()),
+                _ => panic!("Expected a literal"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_false() {
+        let expr = parse(&scan_tokens("false", "<test>"), "<test>");
+        assert_eq!("false", expr.to_string());
+    }
+
+    #[test]
+    fn test_parse_call() {
+        let expr = parse(&scan_tokens("f(1, g(), 2 ? 3 : 4)(5)", "<test>"), "<test>");
+        assert_eq!("(call (call f 1 (call g) (2 ? 3 : 4)) 5)", expr.to_string());
+    }
+
+    #[test]
+    fn test_parse_sequence() {
+        let expr = parse(&scan_tokens("1, -2, (3 ? 4 : 5)", "<test>"), "<test>");
+        assert_eq!("(seq 1 (- 2) (grp (3 ? 4 : 5)))", expr.to_string());
+        match expr.node() {
+            Node::Seq(seq) => assert_eq!(3, seq.exprs.len()),
+            _ => panic!("Expected a sequence"),
+        }
+    }
+
+    #[test]
+    fn test_missing_left_operand() {
+        for (source, message, col) in [
+            (
+                "* 3",
+                "Syntax Error: Binary operator '*' has no left operand.",
+                1,
+            ),
+            (
+                "== 5",
+                "Syntax Error: Binary operator '==' has no left operand.",
+                2,
+            ),
+            (
+                "1 + (+ 2)",
+                "Syntax Error: Binary operator '+' has no left operand.",
+                6,
+            ),
+        ] {
+            let tokens = scan_tokens(source, "<test>");
+            let mut parser = Parser::new(&tokens, "<test>");
+            parser.expression();
+            assert_eq!(1, parser.errors.len());
+            assert_eq!(message, parser.errors[0].message);
+            assert_eq!(col, parser.errors[0].col);
+        }
+
+        // A leading minus is still a negation
+        let tokens = scan_tokens("- 3", "<test>");
+        let mut parser = Parser::new(&tokens, "<test>");
+        assert_eq!("(- 3)", parser.expression().to_string());
+        assert!(parser.errors.is_empty());
+    }
+
+    #[test]
+    fn test_parse_list() {
+        let expr = parse(&scan_tokens("[1, [], [2, 3]][0]", "<test>"), "<test>");
+        assert_eq!("(index (list 1 (list) (list 2 3)) 0)", expr.to_string());
+    }
+
+    #[test]
+    fn test_parse_map() {
+        let expr = parse(
+            &scan_tokens("{ \"a\": 1, \"b\": { } }[\"a\"]", "<test>"),
+            "<test>",
+        );
+        assert_eq!(
+            "(index (map (\"a\" 1) (\"b\" (map))) \"a\")",
+            expr.to_string()
+        );
+    }
+
+    #[test]
+    fn test_parse_coalesce() {
+        let expr = parse(&scan_tokens("a ?? b == c ?? d ? 1 : 2", "<test>"), "<test>");
+        assert_eq!("((?? (?? a (== b c)) d) ? 1 : 2)", expr.to_string());
+    }
+
+    #[test]
+    fn test_parse_div() {
+        let expr = parse(&scan_tokens("1 + 7 div 2 * 3", "<test>"), "<test>");
+        assert_eq!("(+ 1 (* (div 7 2) 3))", expr.to_string());
+    }
+
+    #[test]
+    fn test_parse_bitwise() {
+        let expr = parse(
+            &scan_tokens("1 | 2 ^ 3 & 4 << 5 + 6 == 7 < 8", "<test>"),
+            "<test>",
+        );
+        assert_eq!(
+            "(== (| 1 (^ 2 (& 3 (<< 4 (+ 5 6))))) (< 7 8))",
+            expr.to_string()
+        );
+    }
+
+    #[test]
+    fn test_try_parse() {
+        let expr = try_parse(&scan_tokens("1 + 2", "<test>"), "1 + 2", "<test>");
+        assert_eq!("(+ 1 2)", expr.unwrap().to_string());
+
+        for (source, message) in [
+            (
+                "(1 +",
+                "Syntax Error: Invalid token to start an expression.",
+            ),
+            ("1 2", "Syntax Error: Expect end of expression."),
+            ("f(1, 2", "Syntax Error: Expect ')' after arguments."),
+        ] {
+            let errors = try_parse(&scan_tokens(source, "<test>"), source, "<test>")
+                .err()
+                .unwrap();
+            assert_eq!(message, errors[0].message);
+        }
+    }
+
+    #[test]
+    fn test_error_position() {
+        let source = "1 +\n  2 3";
+        let errors = try_parse(&scan_tokens(source, "<test>"), source, "<test>")
+            .err()
+            .unwrap();
+        assert_eq!(2, errors[0].line);
+        assert_eq!(5, errors[0].col);
+        assert_eq!("  2 3", errors[0].text);
+        assert!(errors[0].to_string().contains("--> <test>:2:5"));
+    }
+
+    #[test]
+    fn test_grp_paren() {
+        let expr = parse(&scan_tokens("\n  (1 + 2)", "<test>"), "<test>");
+        match expr.node() {
+            Node::Grp(grp) => {
+                assert_eq!(2, grp.paren.line);
+                assert_eq!(3, grp.paren.col);
+            }
+            _ => panic!("Expected a group"),
+        }
+    }
+
+    #[test]
+    fn test_unclosed_paren() {
+        let source = "(1 + 2 3 4";
+        let errors = try_parse(&scan_tokens(source, "<test>"), source, "<test>")
+            .err()
+            .unwrap();
+        assert_eq!(1, errors.len());
+        assert_eq!(
+            "Syntax Error: Expect ')' after expression.",
+            errors[0].message
+        );
+        assert_eq!(8, errors[0].col);
+
+        let errors = try_parse(&scan_tokens("(1 + 2", "<test>"), "(1 + 2", "<test>")
+            .err()
+            .unwrap();
+        assert_eq!(
+            "Syntax Error: Expect ')' after expression.",
+            errors[0].message
+        );
+    }
+
+    #[test]
+    fn test_consume() {
+        let tokens = scan_tokens("( 1", "<test>");
+        let mut parser = Parser::new(&tokens, "<test>");
+        let paren = parser.consume(TokenType::LeftParen, "Expect '('.");
+        assert_eq!(TokenType::LeftParen, paren.unwrap().token_type);
+
+        let error = parser
+            .consume(TokenType::RightParen, "Expect ')'.")
+            .err()
+            .unwrap();
+        assert_eq!("Syntax Error: Expect ')'.", error.message);
+        assert_eq!(3, error.col);
+        // A mismatch leaves the token in place
+        assert!(parser.consume(TokenType::Number, "Expect number.").is_ok());
+    }
+
+    #[test]
+    fn test_ternary_missing_colon() {
+        let errors = try_parse(&scan_tokens("a ? b", "<test>"), "a ? b", "<test>")
+            .err()
+            .unwrap();
+        assert_eq!(1, errors.len());
+        assert_eq!(
+            "Syntax Error: Expect ':' in ternary expression.",
+            errors[0].message
+        );
+    }
+
+    #[test]
+    fn test_increment_target() {
+        for (source, message, col) in [
+            ("i++", "Syntax Error: Invalid '++' target.", 3),
+            ("f(1)--", "Syntax Error: Invalid '--' target.", 6),
+        ] {
+            let errors = try_parse(&scan_tokens(source, "<test>"), source, "<test>")
+                .err()
+                .unwrap();
+            assert_eq!(1, errors.len());
+            assert_eq!(message, errors[0].message);
+            assert_eq!(col, errors[0].col);
+        }
+
+        // Without an operand before it, `--` is two minuses
+        for (source, tree) in [
+            ("1 - -2", "(- 1 (- 2))"),
+            ("1--1", "(- 1 (- 1))"),
+            ("--1", "(- (- 1))"),
+            ("a --1", "(- a (- 1))"),
+        ] {
+            let expr = try_parse(&scan_tokens(source, "<test>"), source, "<test>");
+            assert_eq!(tree, expr.ok().unwrap().to_string(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_ternary_associativity() {
+        for (source, tree) in [
+            ("a ? b : c ? d : e", "(a ? b : (c ? d : e))"),
+            ("a ? b ? c : d : e", "(a ? (b ? c : d) : e)"),
+            ("a ? b : c, d", "(seq (a ? b : c) d)"),
+        ] {
+            let expr = try_parse(&scan_tokens(source, "<test>"), source, "<test>");
+            assert_eq!(tree, expr.ok().unwrap().to_string());
+        }
+    }
+
+    #[test]
+    fn test_parse_precedence_matches() {
+        for source in [
+            "1 + 2 * 3 - 4 / 5 div 6",
+            "a ?? b == c < d | e ^ f & g << h + -i * j",
+            "1 - 2 - 3 >> 1 >> 2",
+            "!a == b ? f(1, 2 + 3)[0] : {1: 2 * 3}",
+            "(1 + 2) * 3, x ?? y ?? z",
+            "a or b and c == d ?? e or f",
+        ] {
+            let tokens = scan_tokens(source, "<test>");
+            let expected = try_parse(&tokens, source, "<test>").ok().unwrap();
+            let climbed = parse_precedence(&tokens, source, "<test>").ok().unwrap();
+            assert_eq!(expected.to_string(), climbed.to_string(), "{}", source);
+        }
+
+        for source in ["* 2 + 3", "1 + == 2", "1 +"] {
+            let tokens = scan_tokens(source, "<test>");
+            let expected = try_parse(&tokens, source, "<test>").err().unwrap();
+            let climbed = parse_precedence(&tokens, source, "<test>").err().unwrap();
+            let expected: Vec<String> = expected.iter().map(|e| e.to_string()).collect();
+            let climbed: Vec<String> = climbed.iter().map(|e| e.to_string()).collect();
+            assert_eq!(expected, climbed, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_chained_comparisons() {
+        let message =
+            "Syntax Error: Chained comparisons are not supported; use explicit parentheses.";
+        for climbing in [false, true] {
+            let tokens = scan_tokens("1 < 2 <= 3", "<test>");
+            let mut parser = Parser::new(&tokens, "<test>").reject_chained_comparisons();
+            parser.climbing = climbing;
+            parser.parse();
+            assert_eq!(1, parser.errors.len());
+            assert_eq!(message, parser.errors[0].message);
+            assert_eq!(8, parser.errors[0].col);
+        }
+
+        // Allowed by default, and explicit parentheses are always fine
+        assert!(try_parse(&scan_tokens("1 < 2 < 3", "<test>"), "", "<test>").is_ok());
+        let tokens = scan_tokens("(1 < 2) < 3 == 1 < 2", "<test>");
+        let mut parser = Parser::new(&tokens, "<test>").reject_chained_comparisons();
+        parser.parse();
+        assert!(parser.errors.is_empty());
+    }
+
+    #[test]
+    fn test_source_map() {
+        let source = "1 +\n  \"abc\" * (2)";
+        let tokens = scan_tokens(source, "<test>");
+        let (expr, map) = parse_with_source_map(&tokens, source, "<test>")
+            .ok()
+            .unwrap();
+        let span = |expr: &dyn Expr| map.span(expr.id()).unwrap().clone();
+        assert_eq!(
+            Span {
+                start: (1, 0),
+                end: (2, 13)
+            },
+            span(expr.as_ref())
+        );
+
+        let Node::Bin(sum) = expr.node() else {
+            panic!("expected a sum");
         };
+        let Node::Bin(product) = sum.right.node() else {
+            panic!("expected a product");
+        };
+        // A literal spans exactly its token, quotes included
+        let string = &tokens[2];
+        assert_eq!(
+            Span {
+                start: (string.line, string.span.start),
+                end: (string.line, string.span.end)
+            },
+            span(product.left.as_ref())
+        );
+        assert_eq!(
+            Span {
+                start: (2, 10),
+                end: (2, 13)
+            },
+            span(product.right.as_ref())
+        );
+    }
 
-        expr
+    #[test]
+    fn test_check_two() {
+        let map = scan_tokens("{ \"x\" : 1 }", "<test>");
+        let parser = Parser::new(&map, "<test>");
+        assert!(parser.check_two(TokenType::LeftBrace, TokenType::String));
+        assert!(!parser.check_two(TokenType::LeftBrace, TokenType::Var));
+
+        let block = scan_tokens("{ var x = 1; x }", "<test>");
+        let parser = Parser::new(&block, "<test>");
+        assert!(parser.check_two(TokenType::LeftBrace, TokenType::Var));
+        assert!(!parser.check_two(TokenType::LeftBrace, TokenType::String));
+        assert_eq!(usize::MAX, parser.tokens.curr);
+
+        let errors = try_parse(&block, "{ var x = 1; x }", "<test>")
+            .err()
+            .unwrap();
+        assert_eq!(1, errors.len());
+        assert_eq!(
+            "Syntax Error: Expect expression, a block is not a value.",
+            errors[0].message
+        );
+        assert_eq!(1, errors[0].col);
+
+        let map = "{\"x\": 1}";
+        let expr = try_parse(&scan_tokens(map, "<test>"), map, "<test>");
+        assert_eq!("(map (\"x\" 1))", expr.ok().unwrap().to_string());
     }
 }
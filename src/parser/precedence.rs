@@ -0,0 +1,90 @@
+//! Parses binary operators by precedence climbing instead of with one method
+//! per level. Adding an operator is a single entry in `OPERATORS`.
+
+use super::{start_of, Parser};
+use crate::expression::{Bin, Expr, Logical};
+use crate::token::{Token, TokenType};
+
+/// Binary operators and how tightly they bind, loosest first. Every level is
+/// left associative.
+const OPERATORS: [(TokenType, u8); 20] = [
+    (TokenType::QuestionQuestion, 1),
+    (TokenType::Or, 2),
+    (TokenType::And, 3),
+    (TokenType::EqualEqual, 4),
+    (TokenType::BangEqual, 4),
+    (TokenType::Greater, 5),
+    (TokenType::GreaterEqual, 5),
+    (TokenType::Less, 5),
+    (TokenType::LessEqual, 5),
+    (TokenType::Pipe, 6),
+    (TokenType::Caret, 7),
+    (TokenType::Ampersand, 8),
+    (TokenType::LessLess, 9),
+    (TokenType::GreaterGreater, 9),
+    (TokenType::Plus, 10),
+    (TokenType::Minus, 10),
+    (TokenType::Star, 11),
+    (TokenType::Slash, 11),
+    (TokenType::Div, 11),
+    (TokenType::Percent, 11),
+];
+
+/// How tightly a binary operator binds, or `None` if it is not one.
+pub(crate) fn precedence(token_type: TokenType) -> Option<u8> {
+    OPERATORS
+        .iter()
+        .find(|(t, _)| *t == token_type)
+        .map(|(_, p)| *p)
+}
+
+impl Parser {
+    /// Parses operators binding at least as tightly as `min`, producing the
+    /// same tree as the `coalesce` rule when `min` is 1.
+    pub(super) fn binary(&mut self, min: u8) -> Box<dyn Expr> {
+        let mut expr = match self.next_operator(min) {
+            // A leading minus is a unary negation, not a missing operand
+            Some((op, p)) if op.token_type != TokenType::Minus => {
+                self.tokens.advance(1);
+                self.add_error(
+                    &op,
+                    format!("Binary operator '{}' has no left operand.", op.lexeme),
+                );
+                self.binary(p + 1);
+                self.placeholder(start_of(&op))
+            }
+            _ => self.unary(),
+        };
+
+        while let Some((operator, p)) = self.next_operator(min) {
+            self.tokens.advance(1);
+            self.check_chained(expr.as_ref(), &operator);
+            let right = self.binary(p + 1);
+            let id = self.node_id_from(expr.as_ref());
+            expr = match operator.token_type {
+                TokenType::QuestionQuestion | TokenType::Or | TokenType::And => Box::new(Logical {
+                    id,
+                    left: expr,
+                    operator,
+                    right,
+                }),
+                _ => Box::new(Bin {
+                    id,
+                    left: expr,
+                    operator,
+                    right,
+                }),
+            };
+        }
+        expr
+    }
+
+    /// The next token and its precedence if it's a binary operator binding at
+    /// least as tightly as `min`.
+    fn next_operator(&self, min: u8) -> Option<(Token, u8)> {
+        let next = self.tokens.peek(1)?;
+        precedence(next.token_type)
+            .filter(|p| *p >= min)
+            .map(|p| (next.clone(), p))
+    }
+}
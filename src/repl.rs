@@ -0,0 +1,230 @@
+use crate::scanner::Scanner;
+use crate::token::TokenType;
+use crate::{run, Options};
+use std::fs::{self, OpenOptions};
+use std::io::{self, stdin, stdout, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+/// What the prompt should do after handling a line of input.
+#[derive(Debug, PartialEq)]
+pub enum Dispatch {
+    Continue,
+    Quit,
+}
+
+const HELP: &str = "Enter an expression to evaluate it, or a command:
+  :help     show this message
+  :history  list previously entered lines
+  :quit     leave the prompt";
+
+/// Lines entered at the prompt, mirrored to a file when one is available.
+#[derive(Default)]
+pub struct History {
+    path: Option<PathBuf>,
+    lines: Vec<String>,
+}
+
+impl History {
+    /// Loads the lines saved in `~/.rlox_history`.
+    pub fn load() -> Self {
+        let path = std::env::var_os("HOME").map(|home| Path::new(&home).join(".rlox_history"));
+        let lines = path
+            .as_deref()
+            .and_then(|path| read_history(path).ok())
+            .unwrap_or_default();
+        History { path, lines }
+    }
+
+    /// Records every nonempty line of an entry, saving them to the file.
+    pub fn add(&mut self, entry: &str) {
+        let lines: Vec<&str> = entry.lines().filter(|l| !l.trim().is_empty()).collect();
+        if lines.is_empty() {
+            return;
+        }
+        if let Some(path) = &self.path {
+            if let Err(err) = append_history(path, &lines) {
+                eprintln!("Could not save history: {}", err);
+            }
+        }
+        self.lines.extend(lines.into_iter().map(String::from));
+    }
+}
+
+fn append_history(path: &Path, lines: &[&str]) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    for line in lines {
+        writeln!(file, "{}", line)?;
+    }
+    Ok(())
+}
+
+fn read_history(path: &Path) -> io::Result<Vec<String>> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .map(String::from)
+        .collect())
+}
+
+pub fn run_prompt(options: &Options) {
+    prompt_loop(&mut stdin().lock(), options, &mut History::load())
+}
+
+/// Reads and dispatches lines until `:quit`, the end of input or a read error.
+fn prompt_loop(reader: &mut impl BufRead, options: &Options, history: &mut History) {
+    let input = &mut String::new();
+    loop {
+        print!("> ");
+        Write::flush(&mut stdout()).expect("Flush failed!");
+        input.clear();
+        match read_input(reader, input) {
+            // Zero bytes read means the input was closed
+            Ok(0) => {
+                println!();
+                break;
+            }
+            Ok(_) => {}
+            Err(err) => {
+                println!("{}", err);
+                break;
+            }
+        }
+        // Commands aren't worth recalling, only expressions are
+        if !input.trim_start().starts_with(':') {
+            history.add(input);
+        }
+        if dispatch(input, options, history) == Dispatch::Quit {
+            break;
+        }
+    }
+}
+
+/// Reads a line into `input`, then keeps appending lines after a `... `
+/// prompt while the input is unfinished. Returns the bytes read.
+fn read_input(reader: &mut impl BufRead, input: &mut String) -> io::Result<usize> {
+    let mut total = reader.read_line(input)?;
+    while total > 0 && is_unfinished(input) {
+        print!("... ");
+        Write::flush(&mut stdout()).expect("Flush failed!");
+        match reader.read_line(input)? {
+            0 => break,
+            read => total += read,
+        }
+    }
+    Ok(total)
+}
+
+/// Whether the input stops inside a string, block comment or unclosed
+/// bracket, so that more lines could complete it.
+fn is_unfinished(input: &str) -> bool {
+    if input.trim_start().starts_with(':') {
+        return false;
+    }
+    let mut scanner = Scanner::new("<repl>");
+    let mut depth = 0;
+    for token in scanner.scan_tokens(input) {
+        match token.token_type {
+            TokenType::LeftParen | TokenType::LeftBracket | TokenType::LeftBrace => depth += 1,
+            TokenType::RightParen | TokenType::RightBracket | TokenType::RightBrace => depth -= 1,
+            _ => {}
+        }
+    }
+    match scanner.has_errors() {
+        true => scanner.is_unterminated(),
+        false => depth > 0,
+    }
+}
+
+/// Handles one line of input, intercepting `:` commands before anything
+/// reaches the scanner.
+pub fn dispatch(line: &str, options: &Options, history: &History) -> Dispatch {
+    match line.trim() {
+        ":quit" | ":q" => return Dispatch::Quit,
+        ":help" | ":h" => println!("{}", HELP),
+        ":history" => {
+            for (i, line) in history.lines.iter().enumerate() {
+                println!("{:>4}  {}", i + 1, line);
+            }
+        }
+        command if command.starts_with(':') => {
+            println!("Unknown command '{}', try :help.", command)
+        }
+        _ => run(line.to_string(), "<repl>", options),
+    }
+    Dispatch::Continue
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dispatch() {
+        let options = Options::default();
+        let history = History::default();
+        assert_eq!(Dispatch::Quit, dispatch(":quit\n", &options, &history));
+        assert_eq!(Dispatch::Quit, dispatch("  :q", &options, &history));
+        assert_eq!(Dispatch::Continue, dispatch(":help\n", &options, &history));
+        assert_eq!(Dispatch::Continue, dispatch(":nope\n", &options, &history));
+        assert_eq!(Dispatch::Continue, dispatch("1 + 2\n", &options, &history));
+    }
+
+    #[test]
+    fn test_prompt_loop_ends() {
+        let options = Options::default();
+        // Each of these would loop forever if the end of input were ignored
+        prompt_loop(&mut "".as_bytes(), &options, &mut History::default());
+        prompt_loop(
+            &mut "1 + 2\n3".as_bytes(),
+            &options,
+            &mut History::default(),
+        );
+        prompt_loop(
+            &mut [0xff, b'\n'].as_slice(),
+            &options,
+            &mut History::default(),
+        );
+    }
+
+    #[test]
+    fn test_read_input_continues() {
+        let input = &mut String::new();
+        let reader = &mut "(1 +\n2)\n3\n".as_bytes();
+        read_input(reader, input).unwrap();
+        assert_eq!("(1 +\n2)\n", input);
+
+        let tokens = crate::scanner::scan_tokens(input, "<test>");
+        let expr = crate::parser::try_parse(&tokens, input, "<test>");
+        assert_eq!("(grp (+ 1 2))", expr.ok().unwrap().to_string());
+
+        for (source, unfinished) in [
+            ("\"abc\n", true),
+            ("/* note\n", true),
+            ("{\"a\": [1,\n", true),
+            ("1 + 2)\n", false),
+            ("~\n", false),
+            (":help (\n", false),
+        ] {
+            assert_eq!(unfinished, is_unfinished(source), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_history_file() {
+        let path = std::env::temp_dir().join(format!("rlox_history_{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        append_history(&path, &["1 + 2"]).unwrap();
+        append_history(&path, &["(1 +", "2)"]).unwrap();
+        assert_eq!(vec!["1 + 2", "(1 +", "2)"], read_history(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+
+        let mut history = History::default();
+        history.add("(1 +\n\n2)\n");
+        history.add("\n");
+        assert_eq!(vec!["(1 +", "2)"], history.lines);
+
+        let mut history = History::default();
+        let input = ":help\n1 + 2\n  :history\n:quit\n";
+        prompt_loop(&mut input.as_bytes(), &Options::default(), &mut history);
+        assert_eq!(vec!["1 + 2"], history.lines);
+    }
+}
@@ -1,36 +1,55 @@
 use crate::error_fmt::report_errors;
 use crate::error_fmt::Error;
+use crate::interner::Interner;
 use crate::map;
 use crate::token::{Literal, Token, TokenType};
 use crate::S;
+use number::{radix_prefix, NumberParser};
 use std::collections::hash_map::HashMap;
+use std::ops::Range;
+
+mod number;
 
 type Lexop = fn(&mut Scanner);
 const DO_NOTHING: Lexop = |_s| {};
 
 pub struct Scanner {
     col: usize,
+    /// The line and text of each `///` comment, when collecting them.
+    doc_comments: Option<Vec<(usize, String)>>,
     errors: Vec<Error>,
+    interner: Option<Interner>,
     keywords: HashMap<String, TokenType>,
     lex_func: HashMap<char, Lexop>,
     line: usize,
+    /// Offset of the first byte of the current line, columns are relative to it.
+    line_start: usize,
     start: usize,
     source: Vec<u8>,
+    source_name: String,
     tokens: Vec<Token>,
+    /// Whether the source ended inside a string or block comment.
+    unterminated: bool,
 }
 
 impl Default for Scanner {
     fn default() -> Scanner {
         Scanner {
             source: Vec::new(),
+            source_name: String::new(),
             tokens: Vec::new(),
+            unterminated: false,
             errors: Vec::new(),
+            doc_comments: None,
+            interner: None,
             start: 0,
             col: 0,
             line: 1,
+            line_start: 0,
             keywords: map![
                 { S!("and"), TokenType::And },
                 { S!("class"), TokenType::Class },
+                { S!("div"), TokenType::Div },
                 { S!("else"), TokenType::Else },
                 { S!("false"), TokenType::False },
                 { S!("fun"), TokenType::Fun },
@@ -44,25 +63,30 @@ impl Default for Scanner {
                 { S!("this"), TokenType::This },
                 { S!("true"), TokenType::True },
                 { S!("var"), TokenType::Var },
-                { S!("while"), TokenType::While },
-                { S!("eof"), TokenType::Eof }
+                { S!("while"), TokenType::While }
             ],
             lex_func: map![
                 { '{', Self::left_brace as Lexop },
+                { '&', Self::ampersand as Lexop },
+                { '|', Self::pipe as Lexop },
+                { '^', Self::caret as Lexop },
                 { '}', Self::right_brace as Lexop },
                 { '(', Self::left_paren as Lexop },
                 { ')', Self::right_paren as Lexop },
+                { '[', Self::left_bracket as Lexop },
+                { ']', Self::right_bracket as Lexop },
                 { ',', Self::comma as Lexop },
                 { '.', Self::dot as Lexop },
                 { '-', Self::minus as Lexop },
                 { '+', Self::plus as Lexop },
                 { ';', Self::semicolon as Lexop },
                 { '*', Self::star as Lexop },
+                { '%', Self::percent as Lexop },
                 { '"', Self::string as Lexop },
                 { ' ', DO_NOTHING },
-                { '\r', DO_NOTHING },
+                { '\r', Self::carriage_return as Lexop },
                 { '\t', DO_NOTHING },
-                { '\n', |s| { s.line += 1 } },
+                { '\n', |s| { s.newline(s.col) } },
                 { '!', Self::bang as Lexop },
                 { '=', Self::equal as Lexop },
                 { '>', Self::greater as Lexop },
@@ -75,25 +99,172 @@ impl Default for Scanner {
     }
 }
 
-pub fn scan_tokens(input: &String) -> Vec<Token> {
-    let mut scanner = Scanner::default();
-    scanner.scan_tokens(input.clone());
+/// Scans `input`, printing any lexical errors. Returns no tokens if there were errors.
+pub fn scan_tokens(input: &str, source_name: &str) -> Vec<Token> {
+    try_scan_tokens(input, source_name).unwrap_or_else(|errors| {
+        report_errors(&errors);
+        Vec::new()
+    })
+}
+
+/// Scans `input`, returning every lexical error instead of printing them.
+pub fn try_scan_tokens(input: &str, source_name: &str) -> Result<Vec<Token>, Vec<Error>> {
+    // Scanning eagerly is faster than collecting token_iter, see benches/scanner.rs
+    let mut scanner = Scanner::new(source_name);
+    scanner.scan_tokens(input);
     if scanner.has_errors() {
-        report_errors(&scanner.errors);
+        return Err(scanner.errors);
+    }
+    Ok(scanner.tokens)
+}
+
+/// Reprints `source` with ANSI colors for each token's category and comments
+/// dimmed, leaving whitespace as it was. Lexical errors are ignored.
+pub fn highlight(source: &str) -> String {
+    let mut scanner = Scanner::default();
+    scanner.scan_tokens(source);
+    let mut out = String::new();
+    let mut last = 0;
+    // Whatever lies between tokens is whitespace, comments or bad characters
+    for token in &scanner.tokens {
+        out += &dim(&source[last..token.offset.start]);
+        let lexeme = &source[token.offset.clone()];
+        match token.token_type.color() {
+            Some(color) => out += &format!("\x1b[{}m{}\x1b[0m", color, lexeme),
+            None => out += lexeme,
+        }
+        last = token.offset.end;
+    }
+    out + &dim(&source[last..])
+}
+
+/// Dims the text on each line of `gap`, leaving the whitespace around it.
+fn dim(gap: &str) -> String {
+    let mut out = String::new();
+    for line in gap.split_inclusive('\n') {
+        let text = line.trim();
+        if text.is_empty() {
+            out += line;
+            continue;
+        }
+        let start = line.len() - line.trim_start().len();
+        let end = start + text.len();
+        out += &format!("{}\x1b[2m{}\x1b[0m{}", &line[..start], text, &line[end..]);
+    }
+    out
+}
+
+/// Returns the `///` comments in `source`, each run of them joined into one
+/// text under the line of the token it documents. Lexical errors are ignored.
+pub fn extract_doc_comments(source: &str) -> Vec<(usize, String)> {
+    let mut scanner = Scanner::default().with_doc_comments();
+    scanner.scan_tokens(source);
+    let mut docs: Vec<(usize, String)> = Vec::new();
+    for (line, text) in scanner.doc_comments.take().unwrap_or_default() {
+        // Every comment is followed by at least the Eof token
+        let target = scanner
+            .tokens
+            .iter()
+            .find(|t| t.line > line)
+            .map_or(line, |t| t.line);
+        match docs.last_mut() {
+            Some((last, doc)) if *last == target => {
+                doc.push('\n');
+                doc.push_str(&text);
+            }
+            _ => docs.push((target, text)),
+        }
+    }
+    docs
+}
+
+/// Lazily scans `input`, one lexeme per call to `next`.
+pub fn token_iter(input: &str, source_name: &str) -> impl Iterator<Item = Result<Token, Error>> {
+    let mut scanner = Scanner::new(source_name);
+    scanner.source = input.as_bytes().to_vec();
+    TokenIter {
+        scanner,
+        done: false,
+    }
+}
+
+struct TokenIter {
+    scanner: Scanner,
+    done: bool,
+}
+
+impl Iterator for TokenIter {
+    type Item = Result<Token, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Whitespace and comments produce nothing, so keep scanning until a
+        // lexeme leaves a token or an error behind
+        loop {
+            if !self.scanner.errors.is_empty() {
+                return Some(Err(self.scanner.errors.remove(0)));
+            }
+            if !self.scanner.tokens.is_empty() {
+                return Some(Ok(self.scanner.tokens.remove(0)));
+            }
+            if self.scanner.is_end() {
+                if self.done {
+                    return None;
+                }
+                self.done = true;
+                self.scanner.eof();
+                continue;
+            }
+            self.scanner.scan_lexeme();
+        }
     }
-    scanner.tokens
 }
 
 impl Scanner {
+    pub fn new(source_name: &str) -> Self {
+        Scanner {
+            source_name: S!(source_name),
+            ..Default::default()
+        }
+    }
+
+    /// Interns identifier names, giving each token a symbol id.
+    pub fn with_interner(mut self) -> Self {
+        self.interner = Some(Interner::default());
+        self
+    }
+
+    /// Keeps the text of `///` comments, which are otherwise skipped.
+    pub fn with_doc_comments(mut self) -> Self {
+        self.doc_comments = Some(Vec::new());
+        self
+    }
+
+    pub fn interner(&self) -> Option<&Interner> {
+        self.interner.as_ref()
+    }
+
     fn add_error(&mut self, message: String) {
-        let line =
-            String::from_utf8(self.source.clone()).unwrap_or(S!("Invalid UTF8 chars in source."));
-        self.errors.push(Error::new(
-            S!("Lexical Error: ") + &message,
-            S!(line),
-            self.line.clone(),
-            self.col.clone(),
-        ))
+        let end = self.source[self.line_start..]
+            .iter()
+            .position(|c| *c == b'\n')
+            .map_or(self.source.len(), |len| self.line_start + len);
+        let line = String::from_utf8_lossy(&self.source[self.line_start..end]);
+        self.errors.push(
+            Error::new(
+                S!("Lexical Error: ") + &message,
+                S!(line.trim_end_matches('\r')),
+                self.source_name.clone(),
+                self.line,
+                self.col - self.line_start,
+            )
+            .with_width(self.col - self.start.max(self.line_start)),
+        )
+    }
+
+    /// Moves to the next line, which begins at the given offset.
+    fn newline(&mut self, line_start: usize) {
+        self.line += 1;
+        self.line_start = line_start;
     }
 
     fn add_token(&mut self, token_type: TokenType) {
@@ -103,15 +274,22 @@ impl Scanner {
     fn add_token_literal(&mut self, token_type: TokenType, literal: Option<Literal>) {
         let lexeme = match token_type {
             TokenType::String | TokenType::Number | TokenType::Identifier => {
-                literal.clone().unwrap().to_string()
+                literal.as_ref().unwrap().to_string()
             }
-            _ => String::from_utf8(Vec::from_iter(
-                self.source[self.start..self.col].iter().cloned(),
-            ))
-            .unwrap(),
+            _ => S!(self.lexeme(self.start..self.col)),
         };
-        self.tokens
-            .push(Token::new(token_type, lexeme, literal, self.line, self.col))
+        self.tokens.push(Token::new(
+            token_type,
+            lexeme,
+            literal,
+            self.line,
+            self.start.saturating_sub(self.line_start)..self.col - self.line_start,
+            self.start..self.col,
+        ))
+    }
+
+    fn ampersand(&mut self) {
+        self.add_token(TokenType::Ampersand)
     }
 
     fn advance(&mut self) -> Option<&u8> {
@@ -159,26 +337,44 @@ impl Scanner {
     }
 
     fn block_comment(&mut self) {
-        let res = self.advance_until(|s, c| {
+        // Nested comments are skipped inside, so only the outermost opener is reported
+        let start_line = self.line;
+        if !self.skip_block_comment() {
+            self.unterminated = true;
+            self.add_error(format!(
+                "Unterminated block comment, block comment started on line {}.",
+                start_line
+            ))
+        }
+    }
+
+    /// Advances past the `*/` closing a block comment whose `/*` was already
+    /// consumed. Returns false if the source ends first.
+    fn skip_block_comment(&mut self) -> bool {
+        // Consumes one character per iteration. advance_until would step past the
+        // character following a nested comment, dropping newlines and closers.
+        while let Some(c) = self.peek(false).map(|c| *c as char) {
+            self.advance();
             if c == '\n' {
-                s.line += 1;
-                Ok(false)
-            } else if c == '*' && s.peek(true).is_some_and(|x| (*x as char) == '/') {
-                s.advance();
-                Ok(s.advance_if('/'))
-            } else if c == '/' && s.peek(true).is_some_and(|x| (*x as char) == '*') {
-                s.advance();
-                s.advance();
-                s.block_comment();
-                Ok(false)
-            } else if s.peek(false).is_none() {
-                Err(S!("Unterminated block comment."))
-            } else {
-                Ok(false)
+                self.newline(self.col);
+            } else if c == '*' && self.advance_if('/') {
+                return true;
+            } else if c == '/' && self.advance_if('*') && !self.skip_block_comment() {
+                // An unterminated inner comment leaves the outer one unterminated too
+                return false;
             }
-        });
-        if res.is_err() {
-            self.add_error(res.unwrap_err())
+        }
+        false
+    }
+
+    fn caret(&mut self) {
+        self.add_token(TokenType::Caret)
+    }
+
+    /// Treats `\r\n` as a single newline, a lone `\r` is whitespace.
+    fn carriage_return(&mut self) {
+        if self.advance_if('\n') {
+            self.newline(self.col)
         }
     }
 
@@ -191,14 +387,13 @@ impl Scanner {
     }
 
     fn comment(&mut self) {
-        let _ = self.advance_until(|s, c| {
-            if c == '\n' {
-                s.line += 1;
-                Ok(true)
-            } else {
-                Ok(false)
-            }
-        });
+        let doc = self.advance_if('/');
+        // The newline itself is left for the newline handler to count
+        let _ = self.advance_until(|_s, c| Ok(c == '\n'));
+        if let (true, Some(docs)) = (doc, self.doc_comments.as_mut()) {
+            let text = String::from_utf8_lossy(&self.source[self.start + 3..self.col]);
+            docs.push((self.line, text.trim().to_string()));
+        }
     }
 
     fn dot(&mut self) {
@@ -217,32 +412,56 @@ impl Scanner {
     fn greater(&mut self) {
         let token = if self.advance_if('=') {
             TokenType::GreaterEqual
+        } else if self.advance_if('>') {
+            TokenType::GreaterGreater
         } else {
             TokenType::Greater
         };
         self.add_token(token)
     }
 
-    fn has_errors(&self) -> bool {
-        self.errors.len() != 0
+    pub fn has_errors(&self) -> bool {
+        !self.errors.is_empty()
     }
 
-    fn identifier(&mut self) {
-        let _ = self.advance_until(|_, c| Ok(!c.is_alphanumeric()));
+    /// Whether the source ended inside a string or block comment, so that
+    /// more input could complete it.
+    pub fn is_unterminated(&self) -> bool {
+        self.unterminated
+    }
 
-        let identifier = String::from_utf8(Vec::from_iter(
-            self.source[self.start..self.col].iter().cloned(),
-        ))
-        .unwrap();
+    fn identifier(&mut self) {
+        // Keywords never reach the interner, only names do
+        let _ = self.advance_until(|_, c| Ok(!(c.is_ascii_alphanumeric() || c == '_')));
 
-        match self.keywords.get(&identifier) {
-            Some(tt) => self.add_token(tt.clone()),
+        let identifier = self.lexeme(self.start..self.col);
+        match self.keywords.get(identifier).copied() {
+            Some(tt) => self.add_token(tt),
             None => {
-                self.add_token_literal(TokenType::Identifier, Some(Literal::Identifier(identifier)))
+                let identifier = S!(identifier);
+                let symbol = self.interner.as_mut().map(|i| i.intern(&identifier));
+                self.add_token_literal(
+                    TokenType::Identifier,
+                    Some(Literal::Identifier(identifier)),
+                );
+                self.tokens.last_mut().unwrap().symbol = symbol;
             }
         };
     }
 
+    /// Ends the tokens with a zero width `Eof` after the last character.
+    fn eof(&mut self) {
+        let col = self.col - self.line_start;
+        self.tokens.push(Token::new(
+            TokenType::Eof,
+            S!(""),
+            None,
+            self.line,
+            col..col,
+            self.col..self.col,
+        ));
+    }
+
     fn is_end(&self) -> bool {
         self.col >= self.source.len()
     }
@@ -251,6 +470,10 @@ impl Scanner {
         self.add_token(TokenType::LeftBrace);
     }
 
+    fn left_bracket(&mut self) {
+        self.add_token(TokenType::LeftBracket)
+    }
+
     fn left_paren(&mut self) {
         self.add_token(TokenType::LeftParen)
     }
@@ -258,83 +481,125 @@ impl Scanner {
     fn lesser(&mut self) {
         let token = if self.advance_if('=') {
             TokenType::LessEqual
+        } else if self.advance_if('<') {
+            TokenType::LessLess
         } else {
             TokenType::Less
         };
         self.add_token(token)
     }
 
+    /// Scans `--` only right after something it could decrement, so that
+    /// `1--1` and `--1` still subtract and negate a negation.
     fn minus(&mut self) {
-        self.add_token(TokenType::Minus)
+        let follows_operand = self.tokens.last().is_some_and(|t| {
+            t.offset.end == self.start
+                && matches!(
+                    t.token_type,
+                    TokenType::Identifier | TokenType::RightParen | TokenType::RightBracket
+                )
+        });
+        let token = if follows_operand && self.advance_if('-') {
+            TokenType::MinusMinus
+        } else {
+            TokenType::Minus
+        };
+        self.add_token(token)
     }
 
+    /// Takes everything that could belong to a number, leaving its value to
+    /// `NumberParser`. A typo like `12ab` is one bad literal rather than a
+    /// number followed by a name.
     fn number(&mut self) {
-        let _ = self.advance_until(|s, c| match c.is_digit(10) {
-            true => Ok(false),
-            false => {
-                let mut stop = true;
-                if c == '.' {
-                    let next = s.peek(true);
-                    let res = next.is_some_and(|n| (*n as char).is_digit(10));
-                    match res {
-                        true => stop = false,
-                        false => stop = true,
-                    }
-                };
-                Ok(stop)
-            }
+        let _ = self.advance_until(|s, c| {
+            let taken = s.lexeme(s.start..s.col);
+            let next_is_digit = s.peek(true).is_some_and(u8::is_ascii_digit);
+            let decimal = radix_prefix(taken).is_none();
+            let keep = match c {
+                c if c.is_ascii_alphanumeric() || c == '_' => true,
+                // Only a first '.' followed by a digit belongs to the number,
+                // so "1.2.3" scans as 1.2, '.', 3
+                '.' => next_is_digit && taken.bytes().all(|b| b.is_ascii_digit() || b == b'_'),
+                '+' | '-' => next_is_digit && decimal && taken.ends_with(['e', 'E']),
+                _ => false,
+            };
+            Ok(!keep)
         });
-        let num = String::from_utf8(Vec::from_iter(
-            self.source[self.start..self.col].iter().cloned(),
-        ))
-        .unwrap()
-        .parse::<f64>()
-        .unwrap();
-        self.add_token_literal(TokenType::Number, Some(Literal::Number(num)))
+        let lexeme = self.lexeme(self.start..self.col);
+        match NumberParser::new(lexeme).parse() {
+            Ok(num) => self.add_token_literal(TokenType::Number, Some(Literal::Number(num))),
+            Err(err) => self.add_error(err.to_string()),
+        }
+    }
+
+    /// The source text in the range. Lexemes start and end on ASCII bytes, and
+    /// any other character is skipped whole, so ranges fall on char boundaries.
+    fn lexeme(&self, range: Range<usize>) -> &str {
+        std::str::from_utf8(&self.source[range]).unwrap()
     }
 
     fn peek(&self, one_extra: bool) -> Option<&u8> {
         self.source.get(self.col + one_extra as usize)
     }
 
+    fn percent(&mut self) {
+        self.add_token(TokenType::Percent)
+    }
+
+    fn pipe(&mut self) {
+        self.add_token(TokenType::Pipe)
+    }
+
     fn plus(&mut self) {
-        self.add_token(TokenType::Plus)
+        let token = if self.advance_if('+') {
+            TokenType::PlusPlus
+        } else {
+            TokenType::Plus
+        };
+        self.add_token(token)
     }
 
     fn right_brace(&mut self) {
         self.add_token(TokenType::RightBrace);
     }
 
+    fn right_bracket(&mut self) {
+        self.add_token(TokenType::RightBracket)
+    }
+
     fn right_paren(&mut self) {
         self.add_token(TokenType::RightParen)
     }
 
     fn scan_lexeme(&mut self) {
+        self.start = self.col;
         let c = *self.advance().unwrap() as char;
         match self.lex_func.get(&c) {
             Some(fun) => fun(self),
             None => {
-                if c.is_digit(10) {
+                if c.is_ascii_digit() {
                     self.number()
-                } else if c.is_ascii_alphabetic() {
+                } else if c.is_ascii_alphabetic() || c == '_' {
                     self.identifier()
                 } else {
+                    // One error for the whole character, not each of its bytes
+                    let _ = self.advance_until(|_, c| Ok(!(0x80..0xc0).contains(&(c as u32))));
                     self.add_error(S!("Unexpected character."))
                 }
             }
         }
     }
 
-    pub fn scan_tokens(&mut self, input: String) -> Vec<Token> {
-        self.source = input.into_bytes();
+    pub fn scan_tokens(&mut self, input: &str) -> &[Token] {
+        self.source = input.as_bytes().to_vec();
 
         // Scan one lexeme at a time until reaching end
         while !self.is_end() {
-            self.start = self.col;
             self.scan_lexeme();
         }
+        self.eof();
 
-        self.tokens.clone()
+        &self.tokens
     }
 
     fn semicolon(&mut self) {
@@ -356,44 +621,127 @@ impl Scanner {
     }
 
     fn string(&mut self) {
+        // The character after a backslash never closes the string
+        let mut escaped = false;
+        if self.peek(false).is_none() {
+            self.unterminated = true;
+            return self.add_error(S!("Unterminated string."));
+        }
         let res = self.advance_until(|s, c| {
-            if c == '\n' {
-                s.line += 1
-            };
-            if s.peek(true).is_none() && c != '"' {
+            if s.peek(true).is_none() && (c != '"' || escaped) {
+                // Consume an escaped quote so it does not start another string
+                if escaped {
+                    s.advance();
+                }
+                s.unterminated = true;
                 Err(S!("Unterminated string."))
             } else {
+                // Only count the newline once it is certain to be consumed
+                if c == '\n' {
+                    s.newline(s.col + 1)
+                };
+                if escaped || c == '\\' {
+                    escaped = !escaped;
+                    return Ok(false);
+                }
                 // Advances past the second quote
                 Ok(s.advance_if('"'))
             }
         });
 
-        match res {
+        // + 1 and -1 to cut quotes off
+        match res.and_then(|_| unescape(self.lexeme(self.start + 1..self.col - 1))) {
             Err(message) => self.add_error(message),
-            Ok(_) => {
-                // + 1 and -1 to cut quotes off
-                let string = String::from_utf8(Vec::from_iter(
-                    self.source[self.start + 1..self.col - 1].iter().cloned(),
-                ))
-                .unwrap();
+            Ok(string) => {
                 self.add_token_literal(TokenType::String, Some(Literal::String(string)));
             }
         }
     }
 
     fn question(&mut self) {
-        self.add_token(TokenType::Question)
+        let token = if self.advance_if('?') {
+            TokenType::QuestionQuestion
+        } else {
+            TokenType::Question
+        };
+        self.add_token(token)
+    }
+}
+
+/// Replaces the escape sequences in the body of a string literal.
+fn unescape(raw: &str) -> Result<String, String> {
+    let mut string = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            string.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => string.push('\n'),
+            Some('t') => string.push('\t'),
+            Some('r') => string.push('\r'),
+            Some('0') => string.push('\0'),
+            Some('\\') => string.push('\\'),
+            Some('"') => string.push('"'),
+            Some('x') => string.push(hex_escape(&mut chars)?),
+            Some('u') => string.push(unicode_escape(&mut chars)?),
+            Some(c) => return Err(format!("Unknown escape sequence '\\{}'.", c)),
+            None => return Err(S!("Unterminated escape sequence.")),
+        }
+    }
+    Ok(string)
+}
+
+/// Reads the two hex digits of a `\xNN` escape, limited to ASCII so the
+/// result is always a whole character.
+fn hex_escape(chars: &mut std::str::Chars) -> Result<char, String> {
+    let hex: String = chars.by_ref().take(2).collect();
+    if hex.len() != 2 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!(
+            "Expect two hex digits after '\\x' but got '{}'.",
+            hex
+        ));
+    }
+    match u8::from_str_radix(&hex, 16) {
+        Ok(byte) if byte.is_ascii() => Ok(byte as char),
+        _ => Err(format!(
+            "Hex escape '\\x{}' is outside the ASCII range.",
+            hex
+        )),
+    }
+}
+
+/// Reads the `{XXXX}` part of a `\u{XXXX}` escape.
+fn unicode_escape(chars: &mut std::str::Chars) -> Result<char, String> {
+    if chars.next() != Some('{') {
+        return Err(S!("Expect '{' after '\\u'."));
     }
+    let mut hex = String::new();
+    for c in chars.by_ref() {
+        if c == '}' {
+            return u32::from_str_radix(&hex, 16)
+                .ok()
+                .filter(|_| (1..=6).contains(&hex.len()))
+                .and_then(char::from_u32)
+                .ok_or_else(|| format!("Invalid code point '{}' in unicode escape.", hex));
+        }
+        hex.push(c);
+    }
+    Err(S!("Expect '}' after unicode escape."))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::token::{format_tokens, format_tokens_highlighted};
 
     #[test]
     fn test_peek() {
-        let mut scanner = Scanner::default();
-        scanner.source = S!("123").into_bytes();
+        let mut scanner = Scanner {
+            source: S!("123").into_bytes(),
+            ..Default::default()
+        };
 
         assert_eq!('1', *scanner.peek(false).unwrap() as char);
         assert_eq!('1', *scanner.peek(false).unwrap() as char);
@@ -406,10 +754,18 @@ mod tests {
 
     #[test]
     fn test_advance_until() {
-        let mut scanner = Scanner::default();
-        scanner.source = S!("123").into_bytes();
+        let mut scanner = Scanner {
+            source: S!("123").into_bytes(),
+            ..Default::default()
+        };
         // Should advance until the end of the string
-        let _ = scanner.advance_until(|_s, c| if c.is_digit(10) { Ok(false) } else { Ok(true) });
+        let _ = scanner.advance_until(|_s, c| {
+            if c.is_ascii_digit() {
+                Ok(false)
+            } else {
+                Ok(true)
+            }
+        });
         assert_eq!(scanner.advance(), None)
     }
 
@@ -420,6 +776,8 @@ mod tests {
             TokenType::RightParen,
             TokenType::LeftBrace,
             TokenType::RightBrace,
+            TokenType::LeftBracket,
+            TokenType::RightBracket,
             TokenType::Comma,
             TokenType::Dot,
             TokenType::Minus,
@@ -427,8 +785,8 @@ mod tests {
             TokenType::Semicolon,
             TokenType::Star,
         ];
-        let single_char_string = S!("\t() {},.-+; *\n");
-        let single_char_tokens: Vec<Token> = scan_tokens(&single_char_string);
+        let single_char_string = S!("\t() {}[],.-+; *\n");
+        let single_char_tokens: Vec<Token> = scan_tokens(&single_char_string, "<test>");
         for i in 0..tokens.len() {
             assert_eq!(tokens[i], single_char_tokens[i].token_type)
         }
@@ -444,7 +802,7 @@ mod tests {
             (TokenType::Number, 3.0),
         ];
         let literal_string = S!("12.3 12..3");
-        let literal_tokens: Vec<Token> = scan_tokens(&literal_string);
+        let literal_tokens: Vec<Token> = scan_tokens(&literal_string, "<test>");
         for i in 0..tokens.len() {
             assert_eq!(tokens[i].0, literal_tokens[i].token_type);
             assert_eq!(
@@ -471,7 +829,7 @@ mod tests {
             (TokenType::Dot, ""),
         ];
         let literal_string = S!("\"I\" \"waited\" var \"in\" and \"the \ncinema too\n\".");
-        let literal_tokens: Vec<Token> = scan_tokens(&literal_string);
+        let literal_tokens: Vec<Token> = scan_tokens(&literal_string, "<test>");
         for i in 0..tokens.len() {
             assert_eq!(tokens[i].0, literal_tokens[i].token_type);
             assert_eq!(
@@ -491,6 +849,7 @@ mod tests {
         let tokens = [
             (TokenType::And, ""),
             (TokenType::Class, ""),
+            (TokenType::Div, ""),
             (TokenType::Else, ""),
             (TokenType::False, ""),
             (TokenType::Fun, ""),
@@ -505,13 +864,14 @@ mod tests {
             (TokenType::True, ""),
             (TokenType::Var, ""),
             (TokenType::While, ""),
-            (TokenType::Eof, ""),
+            (TokenType::Identifier, "eof"),
             (TokenType::Identifier, "test"),
             (TokenType::Identifier, "THIS"),
             (TokenType::Identifier, "Let"),
+            (TokenType::Identifier, "_read_file2"),
         ];
-        let literal_string = S!("and class else false fun for if nil or print return super this true var while eof test THIS Let");
-        let literal_tokens: Vec<Token> = scan_tokens(&literal_string);
+        let literal_string = S!("and class div else false fun for if nil or print return super this true var while eof test THIS Let _read_file2");
+        let literal_tokens: Vec<Token> = scan_tokens(&literal_string, "<test>");
         for i in 0..tokens.len() {
             assert_eq!(tokens[i].0, literal_tokens[i].token_type);
             assert_eq!(
@@ -528,11 +888,13 @@ mod tests {
 
     #[test]
     fn test_advance_if() {
-        let mut scanner = Scanner::default();
-        scanner.source = S!("123").into_bytes();
-        assert_eq!(scanner.advance_if('1'), true);
-        assert_eq!(scanner.advance_if('3'), false);
-        assert_eq!(scanner.advance_if('2'), true);
+        let mut scanner = Scanner {
+            source: S!("123").into_bytes(),
+            ..Default::default()
+        };
+        assert!(scanner.advance_if('1'));
+        assert!(!scanner.advance_if('3'));
+        assert!(scanner.advance_if('2'));
     }
 
     #[test]
@@ -545,9 +907,16 @@ mod tests {
             TokenType::Less,
             TokenType::LessEqual,
             TokenType::Greater,
+            TokenType::PlusPlus,
+            TokenType::Plus,
+            TokenType::Identifier,
+            TokenType::MinusMinus,
+            TokenType::Minus,
+            TokenType::PlusPlus,
+            TokenType::Plus,
         ];
-        let single_or_double_string = S!("\t! >= ==!= < <= >\n");
-        let single_or_double_tokens = scan_tokens(&single_or_double_string);
+        let single_or_double_string = S!("\t! >= ==!= < <= >++ + x-- -+++\n");
+        let single_or_double_tokens = scan_tokens(&single_or_double_string, "<test>");
         for i in 0..tokens.len() {
             assert_eq!(tokens[i], single_or_double_tokens[i].token_type)
         }
@@ -558,20 +927,24 @@ mod tests {
         let error = Error {
             message: S!("Lexical Error: Unexpected character."),
             text: S!(""),
+            source_name: S!(""),
             line: 1,
             col: 1,
+            width: 1,
         };
 
         let error2 = Error {
             message: S!("Lexical Error: Unterminated string."),
             text: S!(""),
+            source_name: S!(""),
             line: 1,
             col: 7,
+            width: 1,
         };
 
         let error_string = S!("~ \"test ");
         let mut scanner = Scanner::default();
-        scanner.scan_tokens(error_string);
+        scanner.scan_tokens(&error_string);
         assert_eq!(error.message, scanner.errors[0].message);
         assert_eq!(error.line, scanner.errors[0].line);
         assert_eq!(error.col, scanner.errors[0].col);
@@ -579,4 +952,453 @@ mod tests {
         assert_eq!(error2.line, scanner.errors[1].line);
         assert_eq!(error2.col, scanner.errors[1].col);
     }
+
+    #[test]
+    fn test_error_source_name() {
+        let mut scanner = Scanner::new("test.lox");
+        scanner.scan_tokens("1 +\n~");
+        assert_eq!("test.lox", scanner.errors[0].source_name);
+        assert!(scanner.errors[0].to_string().contains("--> test.lox:2:1"));
+    }
+
+    #[test]
+    fn test_scan_question_tokens() {
+        let tokens = [
+            TokenType::Question,
+            TokenType::QuestionQuestion,
+            TokenType::QuestionQuestion,
+            TokenType::Question,
+            TokenType::Question,
+            TokenType::Eof,
+        ];
+        let scanned = scan_tokens("? ?? ??? ?", "<test>");
+        assert_eq!(tokens.len(), scanned.len());
+        for i in 0..tokens.len() {
+            assert_eq!(tokens[i], scanned[i].token_type)
+        }
+    }
+
+    #[test]
+    fn test_scan_div_and_comments() {
+        let tokens = [
+            TokenType::Number,
+            TokenType::Div,
+            TokenType::Number,
+            TokenType::Slash,
+            TokenType::Identifier,
+            TokenType::Eof,
+        ];
+        let scanned = scan_tokens("7 div 2 // div 2\n/ divide", "<test>");
+        assert_eq!(tokens.len(), scanned.len());
+        for i in 0..tokens.len() {
+            assert_eq!(tokens[i], scanned[i].token_type)
+        }
+    }
+
+    #[test]
+    fn test_scan_bitwise_tokens() {
+        let tokens = [
+            TokenType::Ampersand,
+            TokenType::Pipe,
+            TokenType::Caret,
+            TokenType::LessLess,
+            TokenType::GreaterGreater,
+            TokenType::LessEqual,
+            TokenType::GreaterEqual,
+            TokenType::Less,
+            TokenType::Greater,
+            TokenType::Eof,
+        ];
+        let scanned = scan_tokens("& | ^ << >> <= >= < >", "<test>");
+        assert_eq!(tokens.len(), scanned.len());
+        for i in 0..tokens.len() {
+            assert_eq!(tokens[i], scanned[i].token_type)
+        }
+    }
+
+    #[test]
+    fn test_unterminated_block_comment() {
+        let mut scanner = Scanner::default();
+        scanner.scan_tokens("1\n/* outer\n/* inner */\nstill outer");
+        assert_eq!(1, scanner.errors.len());
+        assert_eq!(
+            "Lexical Error: Unterminated block comment, block comment started on line 2.",
+            scanner.errors[0].message
+        );
+
+        let mut scanner = Scanner::default();
+        scanner.scan_tokens("/* outer\n/* inner");
+        assert_eq!(1, scanner.errors.len());
+        assert_eq!(
+            "Lexical Error: Unterminated block comment, block comment started on line 1.",
+            scanner.errors[0].message
+        );
+    }
+
+    #[test]
+    fn test_nested_block_comment_lines() {
+        let tokens = scan_tokens("/* a\n/* b\nc */\nd */\nx", "<test>");
+        assert_eq!(2, tokens.len());
+        assert_eq!(TokenType::Identifier, tokens[0].token_type);
+        assert_eq!(5, tokens[0].line);
+
+        // Closers directly after a nested comment are not skipped
+        let mut scanner = Scanner::default();
+        assert_eq!(2, scanner.scan_tokens("/* /* */*/ x").len());
+        assert!(scanner.errors.is_empty());
+    }
+
+    #[test]
+    fn test_try_scan_tokens() {
+        let errors = try_scan_tokens("~", "<test>").unwrap_err();
+        assert_eq!(1, errors.len());
+        assert_eq!("Lexical Error: Unexpected character.", errors[0].message);
+
+        let tokens = try_scan_tokens("1 + 2", "<test>").unwrap();
+        assert_eq!(4, tokens.len());
+    }
+
+    #[test]
+    fn test_columns_per_line() {
+        let tokens = scan_tokens("// comment\n  abc", "<test>");
+        assert_eq!(2, tokens[0].line);
+        assert_eq!(5, tokens[0].col);
+    }
+
+    #[test]
+    fn test_token_span() {
+        let tokens = scan_tokens("1 +\n  count", "<test>");
+        assert_eq!(2..7, tokens[2].span);
+        assert_eq!(7, tokens[2].col);
+
+        let error = Error::new(S!("E"), S!("  count"), S!("<test>"), 2, 7).with_width(5);
+        assert!(error.to_string().ends_with("|2.   count\n|     ↑↑↑↑↑ \n"));
+    }
+
+    #[test]
+    fn test_crlf_line_endings() {
+        let tokens = scan_tokens("a\r\nb\r\n\r\n  c", "<test>");
+        assert_eq!((1, 1), (tokens[0].line, tokens[0].col));
+        assert_eq!((2, 1), (tokens[1].line, tokens[1].col));
+        assert_eq!((4, 3), (tokens[2].line, tokens[2].col));
+    }
+
+    #[test]
+    fn test_scan_output_unchanged() {
+        // Expected values were produced before the scanner stopped cloning its
+        // source and tokens
+        let tokens = scan_tokens("var x = \"hi\" + 12.5;\n// note\nfoo(x)", "<test>");
+        let summary: Vec<_> = tokens
+            .iter()
+            .map(|t| (t.token_type, t.lexeme.as_str(), t.line, t.col))
+            .collect();
+        assert_eq!(
+            vec![
+                (TokenType::Var, "var", 1, 3),
+                (TokenType::Identifier, "x", 1, 5),
+                (TokenType::Equal, "=", 1, 7),
+                (TokenType::String, "hi", 1, 12),
+                (TokenType::Plus, "+", 1, 14),
+                (TokenType::Number, "12.5", 1, 19),
+                (TokenType::Semicolon, ";", 1, 20),
+                (TokenType::Identifier, "foo", 3, 3),
+                (TokenType::LeftParen, "(", 3, 4),
+                (TokenType::Identifier, "x", 3, 5),
+                (TokenType::RightParen, ")", 3, 6),
+                (TokenType::Eof, "", 3, 6),
+            ],
+            summary
+        );
+    }
+
+    #[test]
+    fn test_many_errors() {
+        let start = std::time::Instant::now();
+        let errors = try_scan_tokens(&"1 ~\n".repeat(1000), "<test>").unwrap_err();
+        assert!(start.elapsed().as_secs() < 1);
+        assert_eq!(1000, errors.len());
+        assert!(errors.iter().all(|e| e.text == "1 ~"));
+        assert_eq!(1000, errors[999].line);
+    }
+
+    #[test]
+    fn test_interned_identifiers() {
+        let mut scanner = Scanner::default().with_interner();
+        let tokens = scanner.scan_tokens("count + total * count").to_vec();
+        assert_eq!(Some(tokens[0].symbol.unwrap()), tokens[4].symbol);
+        assert_ne!(tokens[0].symbol, tokens[2].symbol);
+        assert_eq!(None, tokens[1].symbol);
+
+        let interner = scanner.interner().unwrap();
+        assert_eq!(2, interner.len());
+        assert_eq!("total", interner.resolve(tokens[2].symbol.unwrap()));
+
+        assert_eq!(None, scan_tokens("count", "<test>")[0].symbol);
+    }
+
+    #[test]
+    fn test_token_iter() {
+        let source = "var x = [1, 2] /* c */ ?? \"s\";\n// c\nx";
+        let collected: Vec<_> = token_iter(source, "<test>")
+            .map(|t| format!("{:?}", t.unwrap()))
+            .collect();
+        let mut scanner = Scanner::default();
+        let scanned: Vec<_> = scanner
+            .scan_tokens(source)
+            .iter()
+            .map(|t| format!("{:?}", t))
+            .collect();
+        assert_eq!(scanned, collected);
+
+        let mut tokens = token_iter("1 ~ 2", "<test>");
+        assert!(tokens.next().unwrap().is_ok());
+        assert!(tokens.next().unwrap().is_err());
+        assert!(tokens.next().unwrap().is_ok());
+        assert_eq!(TokenType::Eof, tokens.next().unwrap().unwrap().token_type);
+        assert!(tokens.next().is_none());
+    }
+
+    #[test]
+    fn test_token_equality() {
+        let tokens = scan_tokens("1 2 1", "<test>");
+        assert_eq!(tokens[0].token_type, tokens[1].token_type);
+        assert_ne!(tokens[0], tokens[1]);
+        // Equal values at different columns are still different tokens
+        assert_ne!(tokens[0], tokens[2]);
+        assert_eq!(tokens, scan_tokens("1 2 1", "<test>"));
+    }
+
+    #[test]
+    fn test_token_categories() {
+        use TokenType::*;
+        for (token_type, category) in [
+            (While, "keyword"),
+            (Div, "keyword"),
+            (Number, "literal"),
+            (Identifier, "literal"),
+            (GreaterEqual, "operator"),
+            (QuestionQuestion, "operator"),
+            (LeftBracket, "punctuation"),
+            (Semicolon, "punctuation"),
+            (Eof, "none"),
+        ] {
+            let categories = [
+                (token_type.is_keyword(), "keyword"),
+                (token_type.is_literal(), "literal"),
+                (token_type.is_operator(), "operator"),
+                (token_type.is_punctuation(), "punctuation"),
+            ];
+            let matched: Vec<&str> = categories
+                .iter()
+                .filter(|(is, _)| *is)
+                .map(|(_, name)| *name)
+                .collect();
+            match category {
+                "none" => assert!(matched.is_empty(), "{:?}", token_type),
+                category => assert_eq!(vec![category], matched, "{:?}", token_type),
+            }
+        }
+    }
+
+    #[test]
+    fn test_format_tokens_highlighted() {
+        let tokens = scan_tokens("(x or 1)", "<test>");
+        let table = format_tokens_highlighted(&tokens);
+        let lines: Vec<&str> = table.lines().collect();
+        assert!(lines[0].starts_with("TYPE "));
+        assert!(lines[1].starts_with("LeftParen   ("));
+        assert!(lines[3].starts_with("\x1b[35mOr        \x1b[0m  or"));
+        assert!(lines[4].starts_with("\x1b[33mNumber    \x1b[0m  1"));
+        assert!(lines[6].starts_with("Eof "));
+
+        // Without the escapes it's the plain table
+        let stripped = ["\x1b[0m", "\x1b[33m", "\x1b[35m"]
+            .iter()
+            .fold(table.clone(), |table, code| table.replace(code, ""));
+        assert_eq!(format_tokens(&tokens), stripped);
+    }
+
+    #[test]
+    fn test_highlight() {
+        let source = "if (x) 12 // count\n\t\"a\nb\"";
+        assert_eq!(
+            "\x1b[35mif\x1b[0m (x) \x1b[33m12\x1b[0m \x1b[2m// count\x1b[0m\n\t\x1b[32m\"a\nb\"\x1b[0m",
+            highlight(source)
+        );
+        assert_eq!("  \n ", highlight("  \n "));
+        assert_eq!("a \x1b[36m+\x1b[0m \x1b[2m~\x1b[0m", highlight("a + ~"));
+    }
+
+    #[test]
+    fn test_format_tokens() {
+        let table = format_tokens(&scan_tokens("count >= 12.5", "<test>"));
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!("TYPE          LEXEME  LINE:COL  LITERAL", lines[0]);
+        assert_eq!(
+            "Identifier    count   1:5       Identifier(\"count\")",
+            lines[1]
+        );
+        assert_eq!("GreaterEqual  >=      1:8", lines[2]);
+        assert_eq!("Number        12.5    1:13      Number(12.5)", lines[3]);
+    }
+
+    #[test]
+    fn test_number_edge_cases() {
+        let tokens = scan_tokens("1.2.3", "<test>");
+        let types: Vec<_> = tokens.iter().map(|t| t.token_type).collect();
+        assert_eq!(
+            vec![
+                TokenType::Number,
+                TokenType::Dot,
+                TokenType::Number,
+                TokenType::Eof
+            ],
+            types
+        );
+        assert_eq!(Some(Literal::Number(1.2)), tokens[0].literal);
+
+        let long = "9".repeat(400);
+        let errors = try_scan_tokens(&long, "<test>").unwrap_err();
+        assert_eq!(
+            "Lexical Error: Number literal is too large.",
+            errors[0].message
+        );
+
+        let long = format!("0.{}", "9".repeat(400));
+        assert_eq!(
+            Some(Literal::Number(1.0)),
+            scan_tokens(&long, "<test>")[0].literal
+        );
+    }
+
+    #[test]
+    fn test_is_unterminated() {
+        for (source, unterminated) in [
+            ("\"abc", true),
+            ("\"", true),
+            ("1 /* note", true),
+            ("\"abc\" ~", false),
+            ("\"\\q\"", false),
+            ("(1 +", false),
+        ] {
+            let mut scanner = Scanner::new("<test>");
+            scanner.scan_tokens(source);
+            assert_eq!(unterminated, scanner.is_unterminated(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_non_ascii_identifier() {
+        let errors = try_scan_tokens("aé + 1", "<test>").unwrap_err();
+        assert_eq!(1, errors.len());
+        assert_eq!("Lexical Error: Unexpected character.", errors[0].message);
+        assert_eq!((3, 2), (errors[0].col, errors[0].width));
+
+        let errors = try_scan_tokens("é😀", "<test>").unwrap_err();
+        assert_eq!(2, errors.len());
+    }
+
+    #[test]
+    fn test_number_forms() {
+        let tokens = scan_tokens("0xff+0b11 - 1_000*2.5e-1 1e3.5", "<test>");
+        let values: Vec<_> = tokens.iter().filter_map(|t| t.literal.clone()).collect();
+        let numbers = [255.0, 3.0, 1000.0, 0.25, 1000.0, 5.0];
+        assert_eq!(numbers.map(Literal::Number).to_vec(), values);
+        assert_eq!(TokenType::Dot, tokens[8].token_type);
+
+        let errors = try_scan_tokens("12ab + 0x", "<test>").unwrap_err();
+        assert_eq!(
+            "Lexical Error: Invalid digit 'a' in number literal '12ab'.",
+            errors[0].message
+        );
+        assert_eq!((4, 4), (errors[0].col, errors[0].width));
+        assert_eq!(
+            "Lexical Error: Expect digits in number literal '0x'.",
+            errors[1].message
+        );
+    }
+
+    #[test]
+    fn test_unterminated_string_at_newline() {
+        let errors = try_scan_tokens("\"abc\n", "<test>").unwrap_err();
+        assert_eq!("Lexical Error: Unterminated string.", errors[0].message);
+        assert_eq!("\"abc", errors[0].text);
+    }
+
+    #[test]
+    fn test_invalid_number_lexeme() {
+        // The scanner never starts a number on '.', so craft the state directly
+        let mut scanner = Scanner {
+            source: b"\n  .".to_vec(),
+            start: 3,
+            col: 4,
+            line: 2,
+            line_start: 1,
+            ..Default::default()
+        };
+        scanner.number();
+        assert!(scanner.tokens.is_empty());
+        assert_eq!(
+            "Lexical Error: Invalid number literal '.'.",
+            scanner.errors[0].message
+        );
+        assert_eq!((2, 3), (scanner.errors[0].line, scanner.errors[0].col));
+    }
+
+    #[test]
+    fn test_string_escapes() {
+        for (source, expected) in [
+            (r#""\u{41}\u{e9}""#, "Aé"),
+            (r#""\u{1F600}!""#, "😀!"),
+            (r#""say \"hi\"\n\t\\""#, "say \"hi\"\n\t\\"),
+            (r#""\x41\x7e\x0A""#, "A~\n"),
+        ] {
+            let tokens = scan_tokens(source, "<test>");
+            assert_eq!(2, tokens.len(), "{}", source);
+            assert_eq!(Some(Literal::String(S!(expected))), tokens[0].literal);
+        }
+
+        for (source, message) in [
+            (
+                r#""\u{D800}""#,
+                "Invalid code point 'D800' in unicode escape.",
+            ),
+            (
+                r#""\u{110000}""#,
+                "Invalid code point '110000' in unicode escape.",
+            ),
+            (r#""\u{}""#, "Invalid code point '' in unicode escape."),
+            (r#""\u41""#, "Expect '{' after '\\u'."),
+            (r#""\u{41""#, "Expect '}' after unicode escape."),
+            (r#""\q""#, "Unknown escape sequence '\\q'."),
+            (r#""\xG""#, "Expect two hex digits after '\\x' but got 'G'."),
+            (r#""\x4""#, "Expect two hex digits after '\\x' but got '4'."),
+            (
+                r#""\xFF""#,
+                "Hex escape '\\xFF' is outside the ASCII range.",
+            ),
+            (r#""open\""#, "Unterminated string."),
+        ] {
+            let errors = try_scan_tokens(source, "<test>").unwrap_err();
+            assert_eq!(format!("Lexical Error: {}", message), errors[0].message);
+        }
+    }
+
+    #[test]
+    fn test_extract_doc_comments() {
+        let source = "/// Adds one.\n///   Really.\nadd(1)\n// not a doc\n\n/// Second\n\nlen(\"a\") /// trailing\n";
+        assert_eq!(
+            vec![
+                (3, S!("Adds one.\nReally.")),
+                (8, S!("Second")),
+                (9, S!("trailing")),
+            ],
+            extract_doc_comments(source)
+        );
+
+        // Without asking for them, doc comments are skipped like any other
+        let mut scanner = Scanner::default();
+        assert_eq!(2, scanner.scan_tokens("/// doc\nx").len());
+        assert!(scanner.doc_comments.is_none());
+    }
 }
@@ -0,0 +1,215 @@
+//! Parses whole numeric lexemes into their values. The scanner only decides
+//! where a number ends, every base and form is handled here.
+
+use std::fmt::Display;
+
+/// Parses a numeric lexeme in one of the forms Lox accepts:
+///
+/// - decimal, with an optional fraction and exponent: `12`, `1.5`, `2.5e-3`
+/// - hexadecimal after `0x`: `0xff`
+/// - binary after `0b`: `0b1010`
+///
+/// Any of them may separate digits with `_`, as in `1_000_000`.
+pub struct NumberParser<'a> {
+    lexeme: &'a str,
+}
+
+impl<'a> NumberParser<'a> {
+    pub fn new(lexeme: &'a str) -> Self {
+        NumberParser { lexeme }
+    }
+
+    pub fn parse(&self) -> Result<f64, NumberError> {
+        if !self.lexeme.starts_with(|c: char| c.is_ascii_digit()) {
+            return Err(self.error(NumberErrorKind::Invalid));
+        }
+        let value = match radix_prefix(self.lexeme) {
+            Some((radix, digits)) => self.integer(digits, radix)?,
+            None => self.decimal()?,
+        };
+        match value.is_finite() {
+            true => Ok(value),
+            false => Err(self.error(NumberErrorKind::TooLarge)),
+        }
+    }
+
+    fn integer(&self, digits: &str, radix: u32) -> Result<f64, NumberError> {
+        self.check_digits(digits, radix)?;
+        Ok(digits
+            .chars()
+            .filter_map(|c| c.to_digit(radix))
+            .fold(0.0, |value, digit| value * radix as f64 + digit as f64))
+    }
+
+    fn decimal(&self) -> Result<f64, NumberError> {
+        let (mantissa, exponent) = match self.lexeme.split_once(['e', 'E']) {
+            Some((mantissa, exponent)) => (mantissa, Some(exponent)),
+            None => (self.lexeme, None),
+        };
+        let (whole, fraction) = match mantissa.split_once('.') {
+            Some((whole, fraction)) => (whole, Some(fraction)),
+            None => (mantissa, None),
+        };
+        self.check_digits(whole, 10)?;
+        if let Some(fraction) = fraction {
+            self.check_digits(fraction, 10)?;
+        }
+        if let Some(exponent) = exponent {
+            let digits = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+            self.check_digits(digits, 10)?;
+        }
+        // Every part is checked, so the standard parser only sees valid input
+        // and gets the rounding right
+        let value = self.lexeme.replace('_', "").parse::<f64>();
+        value.map_err(|_| self.error(NumberErrorKind::Invalid))
+    }
+
+    /// Checks that `digits` is nonempty, in the radix and only separated by
+    /// single `_` between digits.
+    fn check_digits(&self, digits: &str, radix: u32) -> Result<(), NumberError> {
+        if digits.is_empty() {
+            return Err(self.error(NumberErrorKind::MissingDigits));
+        }
+        if let Some(c) = digits.chars().find(|c| *c != '_' && !c.is_digit(radix)) {
+            return Err(self.error(NumberErrorKind::InvalidDigit(c)));
+        }
+        if digits.starts_with('_') || digits.ends_with('_') || digits.contains("__") {
+            return Err(self.error(NumberErrorKind::MisplacedSeparator));
+        }
+        Ok(())
+    }
+
+    fn error(&self, kind: NumberErrorKind) -> NumberError {
+        NumberError {
+            kind,
+            lexeme: self.lexeme.to_string(),
+        }
+    }
+}
+
+/// The radix and the digits after it of a hexadecimal or binary lexeme.
+pub fn radix_prefix(lexeme: &str) -> Option<(u32, &str)> {
+    let prefixes = [("0x", 16), ("0X", 16), ("0b", 2), ("0B", 2)];
+    prefixes.iter().find_map(|(prefix, radix)| {
+        lexeme
+            .strip_prefix(prefix)
+            .map(|digits| (*radix as u32, digits))
+    })
+}
+
+/// Why a numeric lexeme has no value.
+#[derive(Debug, PartialEq)]
+pub struct NumberError {
+    pub kind: NumberErrorKind,
+    pub lexeme: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum NumberErrorKind {
+    /// Not a number at all, e.g. `.`.
+    Invalid,
+    /// A character that isn't a digit of the literal's base, e.g. the `2` in
+    /// `0b12`.
+    InvalidDigit(char),
+    /// A base prefix, fraction or exponent without digits, e.g. `0x` or `1e`.
+    MissingDigits,
+    /// A `_` that isn't between two digits, e.g. `1__0` or `1_`.
+    MisplacedSeparator,
+    /// The value is too large for a number.
+    TooLarge,
+}
+
+impl Display for NumberError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let lexeme = &self.lexeme;
+        match self.kind {
+            NumberErrorKind::Invalid => write!(f, "Invalid number literal '{}'.", lexeme),
+            NumberErrorKind::InvalidDigit(c) => {
+                write!(f, "Invalid digit '{}' in number literal '{}'.", c, lexeme)
+            }
+            NumberErrorKind::MissingDigits => {
+                write!(f, "Expect digits in number literal '{}'.", lexeme)
+            }
+            NumberErrorKind::MisplacedSeparator => write!(
+                f,
+                "Separators in number literal '{}' must be between digits.",
+                lexeme
+            ),
+            NumberErrorKind::TooLarge => write!(f, "Number literal is too large."),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(lexeme: &str) -> Result<f64, NumberErrorKind> {
+        NumberParser::new(lexeme).parse().map_err(|err| err.kind)
+    }
+
+    #[test]
+    fn test_decimal() {
+        assert_eq!(Ok(12.0), parse("12"));
+        assert_eq!(Ok(12.5), parse("12.5"));
+        assert_eq!(Ok(7.0), parse("007"));
+        assert_eq!(Ok(0.1), parse("0.1"));
+        assert_eq!(Err(NumberErrorKind::InvalidDigit('a')), parse("12ab"));
+        assert_eq!(Err(NumberErrorKind::Invalid), parse("."));
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(Ok(255.0), parse("0xff"));
+        assert_eq!(Ok(255.0), parse("0XFF"));
+        assert_eq!(Ok(30.0), parse("0x1e"));
+        assert_eq!(Err(NumberErrorKind::MissingDigits), parse("0x"));
+        assert_eq!(Err(NumberErrorKind::InvalidDigit('g')), parse("0xfg"));
+    }
+
+    #[test]
+    fn test_binary() {
+        assert_eq!(Ok(10.0), parse("0b1010"));
+        assert_eq!(Ok(0.0), parse("0B0"));
+        assert_eq!(Err(NumberErrorKind::InvalidDigit('2')), parse("0b12"));
+        assert_eq!(Err(NumberErrorKind::MissingDigits), parse("0b"));
+    }
+
+    #[test]
+    fn test_scientific() {
+        assert_eq!(Ok(1000.0), parse("1e3"));
+        assert_eq!(Ok(0.0025), parse("2.5E-3"));
+        assert_eq!(Ok(1.5e10), parse("1.5e+10"));
+        assert_eq!(Err(NumberErrorKind::MissingDigits), parse("1e"));
+        assert_eq!(Err(NumberErrorKind::MissingDigits), parse("1e+"));
+        assert_eq!(Err(NumberErrorKind::TooLarge), parse("1e400"));
+    }
+
+    #[test]
+    fn test_separators() {
+        assert_eq!(Ok(1_000_000.0), parse("1_000_000"));
+        assert_eq!(Ok(1234.5678), parse("1_234.567_8"));
+        assert_eq!(Ok(0xffff as f64), parse("0xff_ff"));
+        assert_eq!(Ok(1e10), parse("1e1_0"));
+        assert_eq!(Err(NumberErrorKind::MisplacedSeparator), parse("1__0"));
+        assert_eq!(Err(NumberErrorKind::MisplacedSeparator), parse("1_"));
+        assert_eq!(Err(NumberErrorKind::MisplacedSeparator), parse("0x_1"));
+        assert_eq!(Err(NumberErrorKind::MisplacedSeparator), parse("1_.5"));
+    }
+
+    #[test]
+    fn test_error_messages() {
+        let message = |lexeme| NumberParser::new(lexeme).parse().unwrap_err().to_string();
+        assert_eq!("Invalid number literal '.'.", message("."));
+        assert_eq!(
+            "Invalid digit '2' in number literal '0b12'.",
+            message("0b12")
+        );
+        assert_eq!("Expect digits in number literal '0x'.", message("0x"));
+        assert_eq!(
+            "Separators in number literal '1__0' must be between digits.",
+            message("1__0")
+        );
+        assert_eq!("Number literal is too large.", message("1e400"));
+    }
+}
@@ -1,15 +1,18 @@
 use std::fmt::Display;
+use std::ops::Range;
 
-#[allow(dead_code)]
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum TokenType {
     // Single character tokens.
     Question,
+    QuestionQuestion,
     Colon,
     LeftParen,
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
     Dot,
     Semicolon,
@@ -17,6 +20,10 @@ pub enum TokenType {
     Plus,
     Slash,
     Star,
+    Ampersand,
+    Pipe,
+    Caret,
+    Percent,
 
     // One or two character tokens.
     Bang,
@@ -27,6 +34,10 @@ pub enum TokenType {
     GreaterEqual,
     Less,
     LessEqual,
+    LessLess,
+    GreaterGreater,
+    PlusPlus,
+    MinusMinus,
 
     // Literals.
     Identifier,
@@ -36,6 +47,8 @@ pub enum TokenType {
     // Keywords.
     And,
     Class,
+    // Floor division. A keyword rather than `//` so it can't be confused with a comment.
+    Div,
     Else,
     False,
     Fun,
@@ -53,10 +66,105 @@ pub enum TokenType {
     Eof,
 }
 
+impl TokenType {
+    /// Reserved words, including the word operators `and`, `or` and `div`.
+    pub fn is_keyword(self) -> bool {
+        use TokenType::*;
+        matches!(
+            self,
+            And | Class
+                | Div
+                | Else
+                | False
+                | Fun
+                | For
+                | If
+                | Nil
+                | Or
+                | Print
+                | Return
+                | Super
+                | This
+                | True
+                | Var
+                | While
+        )
+    }
+
+    /// Identifiers and the tokens that carry a value, strings and numbers.
+    pub fn is_literal(self) -> bool {
+        matches!(
+            self,
+            TokenType::Identifier | TokenType::String | TokenType::Number
+        )
+    }
+
+    /// Symbols that combine or assign operands.
+    pub fn is_operator(self) -> bool {
+        use TokenType::*;
+        matches!(
+            self,
+            Question
+                | QuestionQuestion
+                | Colon
+                | Minus
+                | Plus
+                | Slash
+                | Star
+                | Ampersand
+                | Pipe
+                | Caret
+                | Percent
+                | Bang
+                | BangEqual
+                | Equal
+                | EqualEqual
+                | Greater
+                | GreaterEqual
+                | Less
+                | LessEqual
+                | LessLess
+                | GreaterGreater
+                | PlusPlus
+                | MinusMinus
+        )
+    }
+
+    /// Brackets and separators. Together with the other categories these
+    /// cover every type but `Eof`, which is none of them.
+    pub fn is_punctuation(self) -> bool {
+        use TokenType::*;
+        matches!(
+            self,
+            LeftParen
+                | RightParen
+                | LeftBrace
+                | RightBrace
+                | LeftBracket
+                | RightBracket
+                | Comma
+                | Dot
+                | Semicolon
+        )
+    }
+
+    /// The ANSI color code highlighting shows this type in, if any.
+    /// Identifiers and punctuation keep the terminal's own color.
+    pub(crate) fn color(self) -> Option<&'static str> {
+        match self {
+            TokenType::String => Some("32"),
+            TokenType::Number => Some("33"),
+            t if t.is_keyword() => Some("35"),
+            t if t.is_operator() => Some("36"),
+            _ => None,
+        }
+    }
+}
+
 macro_rules! as_variant {
     ($value:expr, $variant:path) => {
         match $value {
-            $variant(x) => Some(x),
+            $variant(x) => Some(x.to_owned()),
             _ => None,
         }
     };
@@ -67,63 +175,159 @@ pub enum Literal {
     Identifier(String),
     String(String),
     Number(f64),
+    Bool(bool),
+    Nil,
 }
 
 impl Literal {
-    pub fn as_number(self) -> Option<f64> {
+    pub fn as_number(&self) -> Option<f64> {
         as_variant!(self, Literal::Number)
     }
 
-    pub fn as_identifier(self) -> Option<String> {
+    pub fn as_identifier(&self) -> Option<String> {
         as_variant!(self, Literal::Identifier)
     }
 
-    pub fn as_string(self) -> Option<String> {
+    pub fn as_string(&self) -> Option<String> {
         as_variant!(self, Literal::String)
     }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        as_variant!(self, Literal::Bool)
+    }
+
+    pub fn is_nil(&self) -> bool {
+        matches!(self, Literal::Nil)
+    }
 }
 
 impl Display for Literal {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Literal::Number(val) => write!(f, "{}", val),
+            Literal::Number(val) => write!(f, "{}", format_number(*val)),
             Literal::Identifier(val) => write!(f, "{}", val),
             Literal::String(val) => write!(f, "{}", val),
+            Literal::Bool(val) => write!(f, "{}", val),
+            Literal::Nil => write!(f, "nil"),
         }
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Token {
     pub token_type: TokenType,
     pub lexeme: String,
     pub line: usize,
     pub col: usize,
+    /// Columns covered by the lexeme on its line, `col` is the end of it.
+    pub span: Range<usize>,
+    /// Bytes covered by the lexeme in the whole source. Unlike `span` this
+    /// holds for strings running over several lines.
+    pub offset: Range<usize>,
     pub literal: Option<Literal>,
+    /// Interned name of an identifier, set when the scanner has an interner.
+    pub symbol: Option<Symbol>,
 }
 
+/// Id of an interned identifier, see `Interner`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Symbol(pub u32);
+
 impl Token {
     pub fn new(
         token_type: TokenType,
         lexeme: String,
         literal: Option<Literal>,
         line: usize,
-        col: usize,
+        span: Range<usize>,
+        offset: Range<usize>,
     ) -> Self {
         Token {
             token_type,
             lexeme,
             literal,
-            col,
+            col: span.end,
+            span,
+            offset,
             line,
+            symbol: None,
         }
     }
 }
 
-impl PartialEq for Token {
-    fn eq(&self, other: &Self) -> bool {
-        other.token_type == self.token_type
+/// The largest integer an `f64` holds exactly, 2^53. Past it neighbouring
+/// integers share a representation.
+pub const MAX_EXACT_INTEGER: f64 = (1u64 << 53) as f64;
+
+/// Formats a number the way Lox prints it: integral values without a
+/// fraction, others with the shortest digits that round trip, and very large
+/// or small magnitudes in exponent notation rather than a wall of zeros.
+///
+/// Integers print in full up to 2^53, the last one an `f64` holds exactly.
+/// Past it the trailing digits would be made up, so those use exponents too.
+pub fn format_number(n: f64) -> String {
+    let abs = n.abs();
+    if abs > MAX_EXACT_INTEGER || (abs != 0.0 && abs < 1e-6) {
+        format!("{:e}", n)
+    } else {
+        format!("{}", n)
+    }
+}
+
+/// Renders tokens as a table of type, lexeme, position and literal value with
+/// aligned columns.
+pub fn format_tokens(tokens: &[Token]) -> String {
+    render_tokens(tokens, false)
+}
+
+/// Like `format_tokens`, coloring each type by its category for terminals.
+pub fn format_tokens_highlighted(tokens: &[Token]) -> String {
+    render_tokens(tokens, true)
+}
+
+fn render_tokens(tokens: &[Token], highlight: bool) -> String {
+    let rows: Vec<[String; 4]> = tokens
+        .iter()
+        .map(|t| {
+            [
+                format!("{:?}", t.token_type),
+                t.lexeme.clone(),
+                format!("{}:{}", t.line, t.col),
+                t.literal
+                    .as_ref()
+                    .map_or(String::new(), |l| format!("{:?}", l)),
+            ]
+        })
+        .collect();
+    let header = ["TYPE", "LEXEME", "LINE:COL", "LITERAL"].map(String::from);
+
+    let mut widths = [0; 4];
+    for row in std::iter::once(&header).chain(&rows) {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    // The header has no token to color
+    let colors = std::iter::once(None).chain(tokens.iter().map(|t| match highlight {
+        true => t.token_type.color(),
+        false => None,
+    }));
+    let mut table = String::new();
+    for (row, color) in std::iter::once(&header).chain(&rows).zip(colors) {
+        let mut line: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:<width$}", cell))
+            .collect();
+        // Colored after padding so the escapes don't count toward the width
+        if let Some(color) = color {
+            line[0] = format!("\x1b[{}m{}\x1b[0m", color, line[0]);
+        }
+        table.push_str(line.join("  ").trim_end());
+        table.push('\n');
     }
+    table
 }
 
 impl Display for Token {
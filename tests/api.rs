@@ -0,0 +1,34 @@
+use rlox::expression::Expr;
+use rlox::interpreter::{Interpreter, Value};
+use rlox::parser::try_parse;
+use rlox::run_string;
+use rlox::scanner::try_scan_tokens;
+
+#[test]
+fn test_pipeline() {
+    let source = "[1, 2, 3][1] * len(\"four\")";
+    let tokens = try_scan_tokens(source, "<test>").unwrap();
+    let expr: Box<dyn Expr> = try_parse(&tokens, source, "<test>").ok().unwrap();
+    assert_eq!(
+        "(* (index (list 1 2 3) 1) (call len \"four\"))",
+        expr.to_string()
+    );
+
+    let mut interpreter = Interpreter::default();
+    let value = interpreter.evaluate(expr.as_ref()).unwrap();
+    assert_eq!(Value::Number(8.0), value);
+    assert_eq!("8", interpreter.stringify(&value));
+}
+
+#[test]
+fn test_run_string_errors() {
+    let errors = run_string("1 + ~").unwrap_err();
+    assert_eq!("Lexical Error: Unexpected character.", errors[0].message);
+    assert_eq!((1, 5), (errors[0].line, errors[0].col));
+
+    let errors = run_string("nope(1)").unwrap_err();
+    assert_eq!(
+        "Runtime Error: Undefined variable 'nope'.",
+        errors[0].message
+    );
+}
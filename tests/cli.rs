@@ -0,0 +1,132 @@
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+fn rlox(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .current_dir(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_check() {
+    let output = rlox(&["--check", "arithmetic.lox"]);
+    assert_eq!(Some(0), output.status.code());
+    assert!(output.stdout.is_empty());
+
+    // Runtime errors aren't found without evaluating
+    assert_eq!(
+        Some(0),
+        rlox(&["--check", "runtime_error.lox"]).status.code()
+    );
+
+    let output = rlox(&["--check", "syntax_error.lox"]);
+    assert_eq!(Some(65), output.status.code());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("Syntax Error: Expect ']' after list elements."));
+}
+
+#[test]
+fn test_json() {
+    let output = rlox(&["--check", "--json", "syntax_error.lox"]);
+    assert_eq!(Some(65), output.status.code());
+    assert_eq!(
+        "[{\"line\":2,\"col\":5,\"message\":\"Syntax Error: Expect ']' after list elements.\",\"text\":\"  3 4]\"}]\n",
+        String::from_utf8(output.stdout).unwrap()
+    );
+
+    let output = rlox(&["--check", "--json", "arithmetic.lox"]);
+    assert_eq!("[]\n", String::from_utf8(output.stdout).unwrap());
+}
+
+#[test]
+fn test_verbose() {
+    // A plain run prints only the result
+    let output = rlox(&["arithmetic.lox"]);
+    assert_eq!("8\n", String::from_utf8(output.stdout).unwrap());
+
+    let output = rlox(&["--verbose", "arithmetic.lox"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("TYPE"));
+    assert!(stdout.ends_with(")\n8\n"));
+}
+
+#[test]
+fn test_stdin() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"(1 + 2)\n* 3")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!("9\n", String::from_utf8(output.stdout).unwrap());
+}
+
+#[test]
+fn test_usage_errors() {
+    let output = rlox(&["a.lox", "b.lox"]);
+    assert_eq!(Some(64), output.status.code());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("Usage Error: Expected at most one path but got 2.\nUsage: rlox"));
+
+    let output = rlox(&["missing.lox"]);
+    assert_eq!(Some(66), output.status.code());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("Usage Error: Could not read 'missing.lox': "));
+}
+
+#[test]
+fn test_fmt() {
+    let output = rlox(&["fmt", "arithmetic.lox"]);
+    assert_eq!(Some(0), output.status.code());
+    let formatted = String::from_utf8(output.stdout).unwrap();
+    assert_eq!("(1 + 2) * 3 - 10 div 4 / 2\n", formatted);
+
+    // Formatting formatted source changes nothing
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .args(["fmt", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(formatted.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(formatted, String::from_utf8(output.stdout).unwrap());
+
+    let output = rlox(&["fmt", "syntax_error.lox"]);
+    assert_eq!(Some(65), output.status.code());
+    assert_eq!(Some(64), rlox(&["fmt", "--write", "-"]).status.code());
+}
+
+#[test]
+fn test_highlight() {
+    let output = rlox(&["highlight", "arithmetic.lox"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("\x1b[2m// Precedence, grouping and floor division\x1b[0m\n"));
+    assert!(stdout.contains(" \x1b[35mdiv\x1b[0m "));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .current_dir(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures"))
+        .args(["highlight", "arithmetic.lox"])
+        .env("NO_COLOR", "1")
+        .output()
+        .unwrap();
+    assert_eq!(
+        std::fs::read_to_string("tests/fixtures/arithmetic.lox").unwrap(),
+        String::from_utf8(output.stdout).unwrap()
+    );
+}
@@ -0,0 +1,34 @@
+use rlox::run_string;
+use std::fs;
+use std::path::Path;
+
+/// Renders what running a program prints: its value or every error.
+fn output(source: &str) -> String {
+    match run_string(source) {
+        Ok(value) => format!("{}\n", value),
+        Err(errors) => errors.iter().map(|e| e.to_string()).collect(),
+    }
+}
+
+/// Runs every `.lox` file in `tests/fixtures` and compares its output to the
+/// matching `.expected` file. Set `BLESS=1` to rewrite the expected files.
+#[test]
+fn test_fixtures() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let mut count = 0;
+    for entry in fs::read_dir(&dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|ext| ext != "lox") {
+            continue;
+        }
+        let source = fs::read_to_string(&path).unwrap();
+        if std::env::var_os("BLESS").is_some() {
+            fs::write(path.with_extension("expected"), output(&source)).unwrap();
+        }
+        let expected = fs::read_to_string(path.with_extension("expected"))
+            .unwrap_or_else(|_| panic!("{} has no .expected file", path.display()));
+        assert_eq!(expected, output(&source), "{}", path.display());
+        count += 1;
+    }
+    assert!(count > 0, "No fixtures found in {}", dir.display());
+}